@@ -1,32 +1,87 @@
+mod meter;
 mod owon;
+mod psu;
 mod rk6006;
+mod sample;
+mod si;
+mod sim;
 
-use argh::FromArgs;
+use argh::{FromArgs, SubCommands};
 use core::panic;
-use owon::mode::Mode;
-use rk6006::{Psu, PsuModbusError};
+use meter::{Current, CurrentMeter, CurrentSample, Measurement, MeterError, MeterFlags};
+use psu::{PowerSupply, PowerSupplyError, Protection, RegulationMode};
+use si::si;
 use snafu::{ensure, OptionExt, Snafu};
 use std::{
-    error::Error,
-    fmt::Debug,
-    time::{Duration, Instant},
+    collections::VecDeque, error::Error, fmt::Debug, path::PathBuf, str::FromStr, time::Duration,
+};
+use tokio::{
+    sync::watch,
+    task::{JoinError, JoinHandle},
+    time::Instant,
 };
-use tokio::{sync::watch, task::JoinHandle};
 use tokio_modbus::SlaveId;
 use tokio_util::sync::CancellationToken;
 
+/// PSU over-voltage protection relative to the rated voltage
+const OVP_FACTOR: f64 = 1.05;
+/// Default PSU constant current setpoint relative to the over-current protection, so that charging
+/// in constant current mode doesn't trip it
+const CC_LIMIT_FACTOR: f64 = 0.9;
+/// Shortest time the PSU may be in constant current mode (and the multimeter may be overloaded)
+/// after a voltage increase, while charging the capacitor
+const CC_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Default grace period for constant current mode relative to the time a voltage step takes to
+/// charge the capacitor at the PSU current limit
+const CC_GRACE_CHARGE_TIME_FACTOR: f64 = 2.0;
+/// File defining multimeter aliases, relative to the config directory, see [`read_meter_aliases`]
+const METER_ALIASES_FILE: &str = "cap-reformer/meter-aliases";
+/// How long to wait for a multimeter reading before treating the multimeter as gone
+const METER_DROPOUT_TIMEOUT: Duration = Duration::from_secs(3);
+/// Number of multimeter samples over which the sample rate is measured
+const SAMPLE_RATE_WINDOW: usize = 10;
+/// Capacitance in µF used for the simulation if none is given
+const SIM_DEFAULT_CAPACITANCE: f64 = 100.0;
+/// Highest simulation speed. The simulation integrates in fixed steps, so higher speeds would keep
+/// the runtime busy.
+const MAX_SIM_SPEED: f64 = 1000.0;
+
 #[derive(Debug, FromArgs)]
 /// Capacitor reformer
+#[argh(
+    note = "Without a subcommand, the arguments are passed to `reform`, e.g. \
+`{command_name} /dev/ttyUSB0 25`."
+)]
+struct Args {
+    #[argh(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    Reform(Config),
+    Discover(DiscoverConfig),
+    Scan(ScanConfig),
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "reform")]
+/// Reform a capacitor
 struct Config {
-    /// serial port to use
+    /// serial port to use, `tcp://host:port` for Modbus TCP, `rtu+tcp://host:port` for Modbus
+    /// RTU over TCP (e.g. ser2net), or `sim` to simulate both the PSU and the multimeter
     #[argh(positional)]
-    serial_port: String,
+    serial_port: PsuTarget,
 
     /// rated voltage of the capacitor in V
     #[argh(positional)]
     voltage: f64,
 
-    /// rated capacitance of the capacitor in µF (optional). This is only used for CV display purposes.
+    /// rated capacitance of the capacitor in µF (optional). This is used for CV display purposes,
+    /// to estimate how long charging takes after a voltage step (see `--cc-grace-period`), and
+    /// for the simulation. Default for the simulation: 100µF
     #[argh(positional)]
     capacitance: Option<f64>,
 
@@ -34,6 +89,97 @@ struct Config {
     #[argh(option, default = "1")]
     slave_id: SlaveId,
 
+    /// baud rate of the serial port. Default: 115200
+    #[argh(option, default = "115200")]
+    baud_rate: u32,
+
+    /// read back PSU setpoints after every write, and retry or abort on mismatch
+    #[argh(switch)]
+    verify_setpoints: bool,
+
+    /// how often to retry a failed PSU request, reconnecting each time, before aborting.
+    /// Default: 5
+    #[argh(option, default = "5")]
+    modbus_retries: u32,
+
+    /// wait time before the first retry of a failed PSU request in ms, doubling with each
+    /// further retry. Default: 250ms
+    #[argh(option, default = "250")]
+    modbus_retry_backoff: u64,
+
+    /// bluetooth adapter to use, by a name in the adapter description (e.g. `hci1`). Default:
+    /// the first adapter
+    #[argh(option)]
+    ble_adapter: Option<String>,
+
+    /// multimeter to connect to, by address, local name or alias. Aliases are defined one
+    /// `alias=address` per line in `~/.config/cap-reformer/meter-aliases` (or below
+    /// `$XDG_CONFIG_HOME`). Default: the first one found
+    #[argh(option)]
+    meter: Option<String>,
+
+    /// how long to scan for the multimeter in seconds. Reconnection attempts scan for at most 4s
+    /// each. Default: 30s
+    #[argh(option, default = "30")]
+    scan_timeout: u64,
+
+    /// how often to try reconnecting to the multimeter after the connection was lost. Reforming
+    /// still aborts once `--max-sample-age` passes without readings. Default: 5
+    #[argh(option, default = "5")]
+    meter_reconnect_attempts: u32,
+
+    /// lower the voltage by `voltage_step` for every few seconds without multimeter readings,
+    /// instead of holding it
+    #[argh(switch)]
+    lower_on_meter_dropout: bool,
+
+    /// cut power and abort reforming if there was no new multimeter sample for this long, in
+    /// seconds. Held readings and readings without a value don't count as new. Limits how long a
+    /// multimeter dropout or hold may last. Default: 30s
+    #[argh(
+        option,
+        default = "Duration::from_secs(30)",
+        from_str_fn(parse_seconds)
+    )]
+    max_sample_age: Duration,
+
+    /// cut power and abort reforming if the multimeter reading doesn't change for this long, in
+    /// seconds, e.g. because the multimeter froze. A steady current shows the same reading for
+    /// a while, so choose it generously. Default: off
+    #[argh(option, from_str_fn(parse_seconds))]
+    max_unchanged_time: Option<Duration>,
+
+    /// cut power and abort reforming if multimeter samples arrive slower than this, in samples
+    /// per second, or 0 for no minimum. Gaps of more than a few seconds count as dropouts, see
+    /// `--max-sample-age`. Default: 1/s
+    #[argh(option, default = "1.0", from_str_fn(parse_sample_rate))]
+    min_sample_rate: f64,
+
+    /// switch the multimeter from autoranging to its current range before reforming
+    #[argh(switch)]
+    lock_meter_range: bool,
+
+    /// command to send to the multimeter before reforming, can be repeated: select, range,
+    /// range-down, auto-range, hold, backlight or relative
+    #[argh(option)]
+    meter_command: Vec<owon::command::Command>,
+
+    /// write every raw multimeter notification to this new file, which must not exist yet. Only
+    /// for a real multimeter, not with the `sim` PSU target or `--replay`
+    #[argh(option)]
+    capture: Option<PathBuf>,
+
+    /// replay the multimeter notifications of a capture file (see `--capture`) instead of
+    /// simulating the multimeter. Requires the `sim` PSU target. Runs as fast as possible on a
+    /// simulated clock, which all timing of the reforming follows, so `--sim-speed` doesn't
+    /// apply. Requires the `fast-replay` feature, unless `--replay-realtime` is given
+    #[argh(option)]
+    replay: Option<PathBuf>,
+
+    /// run `--replay` in real time instead
+    #[argh(switch)]
+    replay_realtime: bool,
+
     /// reform current. increases voltage by `voltage_step` if current falls below this value and
     /// rated voltage has not been reached. Default: 2.5mA
     #[argh(option, default = "2.5")]
@@ -48,19 +194,344 @@ struct Config {
     #[argh(option, default = "0.5")]
     voltage_step: f64,
 
-    /// immediately cut power and abort reforming if current goes above this value. Default: 10mA
+    /// immediately cut power and abort reforming if current goes above this value. Also used as
+    /// the PSU's over-current protection. Default: 10mA
     #[argh(option, default = "10.0")]
     current_limit: f64,
 
-    /// current at which the power supply should go into constant current mode and drop voltage, in milliamps. Default: 30mA
-    #[argh(option, default = "30.0")]
-    psu_current_limit: f64,
+    /// current at which the power supply should go into constant current mode and drop voltage,
+    /// in milliamps. Must be below `--current-limit` also after rounding both to the PSU's
+    /// resolution, as charging would trip the PSU's over-current protection otherwise. Default:
+    /// 90% of `--current-limit`
+    #[argh(option)]
+    psu_current_limit: Option<f64>,
+
+    /// how long the PSU may stay in constant current mode, and the multimeter may be overloaded,
+    /// after a voltage increase, in seconds. Default: twice the time a voltage step takes to
+    /// charge the capacitor at the PSU current limit if the capacitance is given, at least 2s
+    #[argh(option, from_str_fn(parse_seconds))]
+    cc_grace_period: Option<Duration>,
+
+    /// ESR of the simulated capacitor in Ω. Default: 0.5Ω
+    #[argh(option, default = "0.5")]
+    sim_esr: f64,
+
+    /// leakage current of the simulated capacitor at rated voltage before reforming, in mA.
+    /// Default: 20mA
+    #[argh(option, default = "20.0")]
+    sim_leakage: f64,
+
+    /// time constant of the oxide layer re-forming in the simulated capacitor, in seconds.
+    /// Default: 60s
+    #[argh(option, default = "60.0")]
+    sim_forming_time: f64,
+
+    /// voltage above which the simulated capacitor breaks down and shorts (optional)
+    #[argh(option)]
+    sim_breakdown_voltage: Option<f64>,
+
+    /// simulate a shorted capacitor
+    #[argh(switch)]
+    sim_short: bool,
+
+    /// speed of simulated time relative to real time (up to 1000), not used with `--replay`.
+    /// Default: 1
+    #[argh(option, default = "1.0", from_str_fn(parse_sim_speed))]
+    sim_speed: f64,
+}
+
+/// A user defined name for a multimeter address.
+#[derive(Debug, Clone)]
+struct MeterAlias {
+    alias: String,
+    address: String,
+}
+
+impl FromStr for MeterAlias {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (alias, address) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `alias=address`, got `{s}`"))?;
+
+        Ok(MeterAlias {
+            alias: alias.trim().to_owned(),
+            address: address.trim().to_owned(),
+        })
+    }
+}
+
+/// Reads the multimeter aliases from [`METER_ALIASES_FILE`] in `$XDG_CONFIG_HOME`, or in
+/// `~/.config`. A missing file defines no aliases.
+fn read_meter_aliases() -> Result<Vec<MeterAlias>, String> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+    let Some(path) = config_dir.map(|dir| dir.join(METER_ALIASES_FILE)) else {
+        return Ok(Vec::new());
+    };
+
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => parse_meter_aliases(&contents)
+            .map_err(|message| format!("{}: {message}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {e}", path.display())),
+    }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let config: Config = argh::from_env();
+/// Parses one `alias=address` per line, skipping empty lines and `#` comments.
+fn parse_meter_aliases(contents: &str) -> Result<Vec<MeterAlias>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            line.parse()
+                .map_err(|message| format!("line {}: {message}", index + 1))
+        })
+        .collect()
+}
+
+/// Parses a finite number greater than 0.
+fn parse_positive(value: &str) -> Result<f64, String> {
+    let number: f64 = value
+        .parse()
+        .map_err(|e| format!("invalid number `{value}`: {e}"))?;
+    if !(number.is_finite() && number > 0.0) {
+        return Err(format!(
+            "expected a finite number greater than 0, got `{value}`"
+        ));
+    }
+    Ok(number)
+}
+
+/// Parses a finite, positive duration in seconds.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let secs = parse_positive(value)?;
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("invalid duration `{value}`: {e}"))
+}
+
+/// Parses a finite sample rate of at least 0, where 0 turns the check off.
+fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    let rate: f64 = value
+        .parse()
+        .map_err(|e| format!("invalid number `{value}`: {e}"))?;
+    if !(rate.is_finite() && rate >= 0.0) {
+        return Err(format!(
+            "expected a finite number of at least 0, got `{value}`"
+        ));
+    }
+    Ok(rate)
+}
+
+fn parse_sim_speed(value: &str) -> Result<f64, String> {
+    let speed = parse_positive(value)?;
+    if speed > MAX_SIM_SPEED {
+        return Err(format!(
+            "the simulation speed must be at most {MAX_SIM_SPEED}"
+        ));
+    }
+    Ok(speed)
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "discover")]
+/// Search all serial ports for Riden power supplies
+struct DiscoverConfig {
+    /// first Modbus slave ID to probe. Default: 1
+    #[argh(option, default = "1")]
+    first_slave_id: SlaveId,
+
+    /// last Modbus slave ID to probe. Default: 4
+    #[argh(option, default = "4")]
+    last_slave_id: SlaveId,
+
+    /// baud rate to probe, can be given multiple times. Default: all rates supported by Riden
+    /// supplies
+    #[argh(option)]
+    baud_rate: Vec<u32>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "scan")]
+/// Scan for OWON multimeters
+struct ScanConfig {
+    /// bluetooth adapter to use, by a name in the adapter description (e.g. `hci1`). Default:
+    /// the first adapter
+    #[argh(option)]
+    ble_adapter: Option<String>,
+
+    /// how long to scan in seconds. Default: 10s
+    #[argh(option, default = "10")]
+    duration: u64,
+
+    /// number of readings to show per multimeter. Default: 0
+    #[argh(option, default = "0")]
+    readings: usize,
+}
+
+/// The power supply to reform with.
+#[derive(Debug, Clone)]
+enum PsuTarget {
+    Riden(rk6006::Transport),
+    Simulated,
+}
+
+impl FromStr for PsuTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "sim" => PsuTarget::Simulated,
+            _ => PsuTarget::Riden(s.parse()?),
+        })
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = args_from_env();
+
+    // Replays run on a paused clock that skips ahead whenever all tasks wait, so that the
+    // reforming logic sees the recorded timing, however fast the replay runs.
+    let runtime = match &args.command {
+        #[cfg(feature = "fast-replay")]
+        Command::Reform(Config {
+            replay: Some(_),
+            replay_realtime: false,
+            ..
+        }) => tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()?,
+        _ => tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?,
+    };
+
+    runtime.block_on(async {
+        match args.command {
+            Command::Reform(config) => reform(config).await,
+            Command::Discover(config) => discover(config).await,
+            Command::Scan(config) => scan(config).await,
+        }
+    })
+}
+
+/// Parses the command line like [`argh::from_env`], but defaults to the `reform` subcommand, so
+/// `cap-reformer /dev/ttyUSB0 25` keeps working.
+fn args_from_env() -> Args {
+    let mut args: Vec<String> = std::env::args_os()
+        .map(|arg| arg.into_string())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|arg| {
+            eprintln!("Invalid utf8: {}", arg.to_string_lossy());
+            std::process::exit(1)
+        });
+    let Some(program) = args.first().cloned() else {
+        eprintln!("No program name, argv is empty");
+        std::process::exit(1)
+    };
+    let command = std::path::Path::new(&program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(&program);
+
+    let is_subcommand = |arg: &String| {
+        arg == "help" || arg == "--help" || Command::COMMANDS.iter().any(|info| info.name == arg)
+    };
+    if args.get(1).is_some_and(|arg| !is_subcommand(arg)) {
+        args.insert(1, "reform".to_owned());
+    }
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Args::from_args(&[command], &args[1..]).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {command} --help for more information.",
+                    early_exit.output
+                );
+                1
+            }
+        })
+    })
+}
+
+async fn scan(config: ScanConfig) -> Result<(), Box<dyn Error>> {
+    let meters = owon::scan(
+        config.ble_adapter.as_deref(),
+        Duration::from_secs(config.duration),
+    )
+    .await?;
+    if meters.is_empty() {
+        println!("No multimeters found");
+    }
+
+    for meter in meters {
+        let properties = &meter.properties;
+        println!(
+            "{} {}, RSSI: {}{}",
+            properties.address,
+            properties.local_name.as_deref().unwrap_or("[unnamed]"),
+            properties
+                .rssi
+                .map_or(String::from("unknown"), |rssi| format!("{rssi}dBm")),
+            if meter.is_connected {
+                " (connected)"
+            } else {
+                ""
+            },
+        );
 
+        if config.readings > 0 {
+            match owon::sample_readings(&meter, config.readings).await {
+                Ok(readings) => {
+                    for reading in readings {
+                        println!("    {reading}");
+                    }
+                }
+                Err(e) => eprintln!("    Could not read from multimeter: {e}"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn discover(config: DiscoverConfig) -> Result<(), Box<dyn Error>> {
+    let baud_rates = if config.baud_rate.is_empty() {
+        rk6006::BAUD_RATES.to_vec()
+    } else {
+        config.baud_rate
+    };
+
+    let found = rk6006::discover(config.first_slave_id..=config.last_slave_id, &baud_rates).await?;
+    if found.is_empty() {
+        println!("No Riden power supplies found");
+    }
+
+    for psu in found {
+        let model = psu
+            .identification
+            .model()
+            .map_or("unsupported model", |model| model.as_str());
+        println!(
+            "{} (slave ID {}, {} baud): {model}, {}",
+            psu.port, psu.slave_id, psu.baud_rate, psu.identification
+        );
+    }
+
+    Ok(())
+}
+
+async fn reform(config: Config) -> Result<(), Box<dyn Error>> {
     let cancel = CancellationToken::new();
     let reform_task_cancel_token = cancel.clone();
 
@@ -72,116 +543,459 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     println!("Starting reforming with config:\n{:#?}", config);
 
-    println!("Connecting to PSU...");
-    let mut psu = rk6006::open_psu_modbus(config.serial_port.clone(), config.slave_id).await?;
-    let (bt_tx, bt_rx) = watch::channel(None);
-
-    println!("Connecting to Multimeter...");
-    let mut bt_task = owon::start_bt_message_stream_task(cancel.clone(), bt_tx).await?;
+    if config
+        .psu_current_limit
+        .is_some_and(|psu_current_limit| psu_current_limit >= config.current_limit)
+    {
+        return Err(
+            "--psu-current-limit must be below --current-limit, as charging would trip \
+                    the PSU's over-current protection otherwise"
+                .into(),
+        );
+    }
 
-    let mut logic_task: JoinHandle<Result<(), Box<dyn Error + Send + Sync>>> =
-        tokio::spawn(async move {
-            let res = reform_cap(&mut psu, reform_task_cancel_token, bt_rx, &config).await;
-            psu.set_output(false).await?;
-            let _ = psu.disconnect().await;
-            res?;
+    let (logic_task, bt_task) = match config.serial_port.clone() {
+        PsuTarget::Simulated => {
+            if !config.meter_command.is_empty() {
+                return Err("multimeter commands require a real multimeter".into());
+            }
+            if config.capture.is_some() {
+                return Err("capturing requires a real multimeter".into());
+            }
 
-            Ok(())
-        });
+            println!("Simulating PSU and multimeter...");
+            let (psu, meter) = new_sim_pair(&config);
 
-    tokio::select! {
-        res = &mut bt_task => {
-            match res {
-                Ok(Err(e)) => {
-                    eprintln!("Error in BT task: {:#?}", e);
+            if let Some(replay) = &config.replay {
+                if config.sim_speed != 1.0 {
+                    return Err(
+                        "--sim-speed doesn't apply to --replay, which runs in real time \
+                                or on a simulated clock as fast as possible"
+                            .into(),
+                    );
                 }
-                Err(e) => {
-                    eprintln!("Join error in BT task: {:#?}", e);
+                if !config.replay_realtime && !cfg!(feature = "fast-replay") {
+                    return Err("replaying as fast as possible requires the `fast-replay` \
+                                feature, use --replay-realtime to replay in real time"
+                        .into());
                 }
-                _ => {}
+                println!("Replaying multimeter readings from {}...", replay.display());
+                let records = owon::capture::read(replay)?;
+                let (reading_tx, reading_rx) = watch::channel(None);
+                let replay_task = owon::start_replay_task(cancel.clone(), reading_tx, records);
+
+                let logic_task = tokio::spawn(run_reform(
+                    psu,
+                    owon::Meter::new(reading_rx, None),
+                    reform_task_cancel_token,
+                    config,
+                ));
+                (logic_task, Some(replay_task))
+            } else {
+                let logic_task =
+                    tokio::spawn(run_reform(psu, meter, reform_task_cancel_token, config));
+                (logic_task, None)
+            }
+        }
+        PsuTarget::Riden(_) if config.replay.is_some() => {
+            return Err("replaying a capture requires the `sim` PSU target".into());
+        }
+        PsuTarget::Riden(transport) => {
+            println!("Connecting to PSU at {transport}...");
+            let mut psu =
+                rk6006::open_psu_modbus(&transport, config.slave_id, config.baud_rate).await?;
+            psu.set_verify_setpoints(config.verify_setpoints);
+            psu.set_retry_policy(rk6006::RetryPolicy {
+                max_retries: config.modbus_retries,
+                initial_backoff: Duration::from_millis(config.modbus_retry_backoff),
+            });
+            let (bt_tx, bt_rx) = watch::channel(None);
+
+            let capture = config
+                .capture
+                .as_deref()
+                .map(|path| {
+                    owon::capture::CaptureWriter::create(path)
+                        .map_err(|e| format!("capture file {}: {e}", path.display()))
+                })
+                .transpose()?;
+
+            let meter = match &config.meter {
+                Some(meter) => Some(
+                    read_meter_aliases()?
+                        .into_iter()
+                        .find(|alias| alias.alias == *meter)
+                        .map_or_else(|| meter.clone(), |alias| alias.address),
+                ),
+                None => None,
+            };
+
+            println!("Connecting to Multimeter...");
+            let selector = owon::DeviceSelector {
+                adapter: config.ble_adapter.clone(),
+                meter,
+                scan_timeout: Duration::from_secs(config.scan_timeout),
+                reconnect_attempts: config.meter_reconnect_attempts,
+            };
+            let reconnect_budget = owon::reconnect_budget(&selector);
+            if config.max_sample_age < reconnect_budget {
+                eprintln!(
+                    "Warning: --max-sample-age of {:?} is shorter than reconnecting to the \
+                     multimeter may take ({reconnect_budget:?}), reforming aborts before all \
+                     reconnection attempts are used",
+                    config.max_sample_age
+                );
             }
+            let (bt_task, meter_control) =
+                owon::start_bt_message_stream_task(cancel.clone(), bt_tx, &selector, capture)
+                    .await?;
+            let mut meter = owon::Meter::new(bt_rx, Some(meter_control));
+            meter.prepare(config.lock_meter_range).await?;
+            for command in &config.meter_command {
+                println!("Sending {command:?} to the multimeter...");
+                meter.send(*command).await?;
+            }
+
+            let logic_task = tokio::spawn(run_reform(psu, meter, reform_task_cancel_token, config));
+            (logic_task, Some(bt_task))
+        }
+    };
+
+    supervise(logic_task, bt_task, cancel).await;
+
+    Ok(())
+}
+
+/// Creates the simulated PSU and multimeter described by the `--sim-*` options.
+fn new_sim_pair(config: &Config) -> (sim::SimPsu, sim::SimMeter) {
+    sim::new_pair(
+        sim::CapacitorParams {
+            capacitance: config.capacitance.unwrap_or(SIM_DEFAULT_CAPACITANCE) / 1e6,
+            esr: config.sim_esr,
+            rated_voltage: config.voltage,
+            initial_leakage: config.sim_leakage / 1000.0,
+            forming_time: config.sim_forming_time,
+            breakdown_voltage: config.sim_breakdown_voltage,
+            shorted: config.sim_short,
+        },
+        config.sim_speed,
+    )
+}
+
+/// Waits for the reforming logic to finish, cancelling it if the BT task ends first.
+async fn supervise(
+    mut logic_task: JoinHandle<Result<(), Box<dyn Error + Send + Sync>>>,
+    bt_task: Option<JoinHandle<Result<(), owon::StartBtMessageStreamError>>>,
+    cancel: CancellationToken,
+) {
+    let Some(mut bt_task) = bt_task else {
+        print_task_result("logic", logic_task.await);
+        return;
+    };
+
+    tokio::select! {
+        res = &mut bt_task => {
+            print_task_result("BT", res);
             cancel.cancel();
             let _ = logic_task.await;
         }
         res = &mut logic_task => {
-            match res {
-                Ok(Err(e)) => {
-                    eprintln!("Error in logic task: {:#?}", e);
-                }
-                Err(e) => {
-                    eprintln!("Join error in logic task: {:#?}", e);
-                }
-                _ => {}
-            }
+            print_task_result("logic", res);
             cancel.cancel();
             let _ = bt_task.await;
         }
     }
+}
 
-    Ok(())
+fn print_task_result<E: Debug>(task: &str, res: Result<Result<(), E>, JoinError>) {
+    match res {
+        Ok(Err(e)) => {
+            eprintln!("Error in {task} task: {:#?}", e);
+        }
+        Err(e) => {
+            eprintln!("Join error in {task} task: {:#?}", e);
+        }
+        _ => {}
+    }
 }
 
 #[derive(Debug, Snafu)]
 enum ReformCapError {
     #[snafu(context(false))]
-    PsuModbus { source: PsuModbusError },
+    Psu { source: PowerSupplyError },
     #[snafu(context(false))]
-    BtChannelClosed { source: watch::error::RecvError },
-    /// No reading available from the multimeter
-    NoBtReading,
-    #[snafu(display("Wrong reading mode, got {mode:#?}"))]
-    WrongReadingMode { mode: owon::mode::Mode },
+    Meter { source: MeterError },
 
     /// Aborted reforming because the current limit was exceeded
     CapCurrentLimitExceeded,
+    /// Aborted reforming because the multimeter stayed overloaded, the current is above its range
+    MeterOverloaded,
+    #[snafu(display(
+        "Aborted reforming, no new multimeter sample for {age:?}, the multimeter is gone, held or \
+         shows no value"
+    ))]
+    MeterSamplesStale { age: Duration },
+    /// Aborted reforming, a multimeter sample was loaded instead of received, its age is unknown
+    MeterSampleNotReceived,
+    #[snafu(display(
+        "Aborted reforming, multimeter reading unchanged for {duration:?}, the multimeter may be \
+         frozen"
+    ))]
+    MeterReadingUnchanged { duration: Duration },
+    #[snafu(display(
+        "Aborted reforming, multimeter sample rate {rate:.2}/s is below the minimum of \
+         {min_rate:.2}/s"
+    ))]
+    MeterSampleRateTooLow { rate: f64, min_rate: f64 },
+    #[snafu(display("PSU protection tripped: {protection:?}"))]
+    PsuProtectionTripped { protection: Protection },
+    /// PSU output was switched off unexpectedly
+    PsuOutputDisabled,
+    /// PSU switched to constant current mode, the capacitor draws more than the PSU current limit
+    PsuConstantCurrent,
+    #[snafu(display(
+        "PSU over-voltage protection reads back as {actual}V instead of {expected}V"
+    ))]
+    VoltageProtectionMismatch { expected: f64, actual: f64 },
+    #[snafu(display(
+        "PSU over-current protection reads back as {actual}A instead of {expected}A"
+    ))]
+    CurrentProtectionMismatch { expected: f64, actual: f64 },
+    #[snafu(display(
+        "PSU current limit of {} doesn't fit below the over-current protection of {} at the \
+         PSU's resolution of {}, raise --current-limit",
+        si(*psu_current, "A"),
+        si(*current_protection, "A"),
+        si(*resolution, "A")
+    ))]
+    PsuCurrentLimitUnrepresentable {
+        psu_current: f64,
+        current_protection: f64,
+        resolution: f64,
+    },
+}
+
+async fn run_reform(
+    mut psu: impl PowerSupply,
+    mut meter: impl CurrentMeter,
+    cancel: CancellationToken,
+    config: Config,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (old_voltage_protection, old_current_protection) =
+        psu.voltage_and_current_protection().await?;
+
+    let res = match configure_protection(&mut psu, &config).await {
+        Ok(psu_current) => reform_cap(&mut psu, &mut meter, cancel, &config, psu_current).await,
+        Err(e) => Err(e),
+    };
+
+    // Clean up as far as possible also if a step fails, e.g. after the PSU stopped responding,
+    // and report the error of reforming first.
+    let output_off = psu.set_output(false).await;
+    if let Err(e) = &output_off {
+        eprintln!("Error switching off the PSU output: {e:?}");
+    }
+    println!(
+        "Restoring PSU protection to {old_voltage_protection:.2}V, {}",
+        si(old_current_protection, "A")
+    );
+    let voltage_protection = psu.set_voltage_protection(old_voltage_protection).await;
+    if let Err(e) = &voltage_protection {
+        eprintln!("Error restoring the PSU over-voltage protection: {e:?}");
+    }
+    let current_protection = psu.set_current_protection(old_current_protection).await;
+    if let Err(e) = &current_protection {
+        eprintln!("Error restoring the PSU over-current protection: {e:?}");
+    }
+    let _ = psu.disconnect().await;
+
+    res?;
+    output_off?;
+    voltage_protection?;
+    current_protection?;
+
+    Ok(())
+}
+
+/// Programs the PSU's own over-voltage and over-current protection as a backstop, in case this
+/// process or the multimeter connection stalls while the output is on. Both are read back, and
+/// must match to within half a step of the PSU's resolution.
+///
+/// Returns the constant current setpoint in A. It is rounded to the PSU's resolution, and checked
+/// to stay below the over-current protection after rounding.
+async fn configure_protection(
+    psu: &mut impl PowerSupply,
+    config: &Config,
+) -> Result<f64, ReformCapError> {
+    let limits = psu.limits();
+    let voltage_protection =
+        limits.round_voltage((config.voltage * OVP_FACTOR).min(limits.max_voltage_protection));
+    let current_protection = limits.round_current(config.current_limit / 1000.0);
+    let psu_current = limits.round_current(
+        config
+            .psu_current_limit
+            .unwrap_or(config.current_limit * CC_LIMIT_FACTOR)
+            / 1000.0,
+    );
+    ensure!(
+        psu_current > 0.0 && psu_current < current_protection,
+        PsuCurrentLimitUnrepresentableSnafu {
+            psu_current,
+            current_protection,
+            resolution: limits.current_resolution,
+        }
+    );
+
+    println!(
+        "Setting PSU protection to {voltage_protection:.2}V, {}",
+        si(current_protection, "A")
+    );
+    psu.set_voltage_protection(voltage_protection).await?;
+    psu.set_current_protection(current_protection).await?;
+
+    let (actual_voltage, actual_current) = psu.voltage_and_current_protection().await?;
+    ensure!(
+        (actual_voltage - voltage_protection).abs() < limits.voltage_resolution / 2.0,
+        VoltageProtectionMismatchSnafu {
+            expected: voltage_protection,
+            actual: actual_voltage,
+        }
+    );
+    ensure!(
+        (actual_current - current_protection).abs() < limits.current_resolution / 2.0,
+        CurrentProtectionMismatchSnafu {
+            expected: current_protection,
+            actual: actual_current,
+        }
+    );
+
+    Ok(psu_current)
 }
 
 async fn reform_cap(
-    psu: &mut Psu,
+    psu: &mut impl PowerSupply,
+    meter: &mut impl CurrentMeter,
     cancel: CancellationToken,
-    mut reading_rx: watch::Receiver<Option<owon::reading::Reading>>,
     config: &Config,
+    psu_current: f64,
 ) -> Result<(), ReformCapError> {
     let Config {
         serial_port: _,
         slave_id: _,
+        baud_rate: _,
+        verify_setpoints: _,
+        modbus_retries: _,
+        modbus_retry_backoff: _,
+        ble_adapter: _,
+        meter: _,
+        scan_timeout: _,
+        meter_reconnect_attempts: _,
+        lower_on_meter_dropout,
+        max_sample_age,
+        max_unchanged_time,
+        min_sample_rate,
+        lock_meter_range: _,
+        meter_command: _,
+        capture: _,
+        replay: _,
+        replay_realtime: _,
         capacitance,
         voltage: rated_voltage,
         reform_current,
         finish_current,
         voltage_step,
         current_limit,
-        psu_current_limit,
+        psu_current_limit: _,
+        cc_grace_period,
+        sim_esr: _,
+        sim_leakage: _,
+        sim_forming_time: _,
+        sim_breakdown_voltage: _,
+        sim_short: _,
+        sim_speed: _,
     } = *config;
 
     let reform_current_milliamps = reform_current;
     let finish_current_milliamps = finish_current;
     let current_limit_milliamps = current_limit;
 
-    let mut last_voltage_increase = Instant::now();
+    let cc_grace_period = cc_grace_period
+        .unwrap_or_else(|| default_cc_grace_period(capacitance, voltage_step, psu_current));
+    println!("Allowing constant current mode for {cc_grace_period:?} after each voltage increase");
+    let mut charging = ChargingPeriod {
+        last_voltage_increase: Instant::now(),
+        duration: cc_grace_period,
+    };
+    let mut meter_flags = MeterFlags::default();
+    let mut first_timestamp = None;
+    let mut monitor = SampleMonitor::new(max_sample_age, max_unchanged_time, min_sample_rate);
 
     let mut curr_voltage = 0.0;
     psu.set_voltage(curr_voltage).await?;
     tokio::time::sleep(Duration::from_millis(500)).await;
-    psu.set_current(psu_current_limit / 1000.0).await?;
+    psu.set_current(psu_current).await?;
     tokio::time::sleep(Duration::from_millis(500)).await;
     psu.set_output(true).await?;
 
+    let status = psu.status().await?.value;
+    println!(
+        "PSU input: {:.2}V, temperature: {}°C",
+        status.input_voltage, status.temperature
+    );
+
     println!("Reforming...");
     loop {
         if cancel.is_cancelled() {
             return Ok(());
         }
-        let milliamps = current_milliamps(&mut reading_rx).await?;
-        print_measurement(rated_voltage, capacitance, curr_voltage, milliamps);
+        let Some(sample) = next_sample_or_hold(
+            psu,
+            meter,
+            &cancel,
+            &mut monitor,
+            &mut curr_voltage,
+            lower_on_meter_dropout.then_some(voltage_step),
+            &charging,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+        let Some(milliamps) = current_milliamps(
+            psu,
+            &sample,
+            curr_voltage,
+            current_limit_milliamps,
+            &charging,
+            &mut meter_flags,
+        )
+        .await?
+        else {
+            check_psu_status(psu, &charging).await?;
+            continue;
+        };
+        let since_start = sample
+            .timestamp
+            .duration_since(*first_timestamp.get_or_insert(sample.timestamp))
+            .unwrap_or_default();
+        print_measurement(
+            since_start,
+            rated_voltage,
+            capacitance,
+            curr_voltage,
+            milliamps,
+        );
 
         ensure!(
             milliamps < current_limit_milliamps,
             CapCurrentLimitExceededSnafu
         );
+        check_psu_status(psu, &charging).await?;
 
         if milliamps < reform_current_milliamps
-            && last_voltage_increase.elapsed() > Duration::from_secs(1)
+            && sample
+                .received_at
+                .is_some_and(|at| charging.time_since_increase(at) > Duration::from_secs(1))
         {
             if curr_voltage == rated_voltage {
                 break;
@@ -189,26 +1003,76 @@ async fn reform_cap(
 
             curr_voltage = (curr_voltage + voltage_step).min(rated_voltage);
             psu.set_voltage(curr_voltage).await?;
-            last_voltage_increase = Instant::now();
+            charging.last_voltage_increase = Instant::now();
         }
     }
 
     println!(
-        "Target voltage reached, waiting to reach target current (< {:.3}mA)...",
-        finish_current_milliamps
+        "Target voltage reached, waiting to reach target current (< {})...",
+        si(finish_current_milliamps / 1000.0, "A")
     );
 
     loop {
         if cancel.is_cancelled() {
             break;
         }
-        let milliamps = current_milliamps(&mut reading_rx).await?;
-        print_measurement(rated_voltage, capacitance, curr_voltage, milliamps);
+        let Some(sample) = next_sample_or_hold(
+            psu,
+            meter,
+            &cancel,
+            &mut monitor,
+            &mut curr_voltage,
+            lower_on_meter_dropout.then_some(voltage_step),
+            &charging,
+        )
+        .await?
+        else {
+            return Ok(());
+        };
+        let Some(milliamps) = current_milliamps(
+            psu,
+            &sample,
+            curr_voltage,
+            current_limit_milliamps,
+            &charging,
+            &mut meter_flags,
+        )
+        .await?
+        else {
+            check_psu_status(psu, &charging).await?;
+            continue;
+        };
+        let since_start = sample
+            .timestamp
+            .duration_since(*first_timestamp.get_or_insert(sample.timestamp))
+            .unwrap_or_default();
+        print_measurement(
+            since_start,
+            rated_voltage,
+            capacitance,
+            curr_voltage,
+            milliamps,
+        );
 
         ensure!(
             milliamps < current_limit_milliamps,
             CapCurrentLimitExceededSnafu
         );
+        check_psu_status(psu, &charging).await?;
+
+        if curr_voltage < rated_voltage {
+            // The voltage was lowered while the multimeter was gone, step back up first.
+            if milliamps < reform_current_milliamps
+                && sample
+                    .received_at
+                    .is_some_and(|at| charging.time_since_increase(at) > Duration::from_secs(1))
+            {
+                curr_voltage = (curr_voltage + voltage_step).min(rated_voltage);
+                psu.set_voltage(curr_voltage).await?;
+                charging.last_voltage_increase = Instant::now();
+            }
+            continue;
+        }
 
         if milliamps < finish_current_milliamps {
             println!("Reforming complete");
@@ -219,31 +1083,735 @@ async fn reform_cap(
     Ok(())
 }
 
+/// Extracts the current in mA from a sample, or `None` if it has no value or is held. An
+/// overloaded multimeter is tolerated while charging after a voltage increase, but fails the run
+/// otherwise, as the actual current is unknown. In that case, the current measured by the PSU is
+/// checked against `current_limit_milliamps` instead.
+///
+/// Reports changes of the multimeter flags compared to `last_flags`.
 async fn current_milliamps(
-    reading_rx: &mut watch::Receiver<Option<owon::reading::Reading>>,
-) -> Result<f64, ReformCapError> {
-    reading_rx.changed().await?;
-    let reading = reading_rx
-        .borrow_and_update()
-        .as_ref()
-        .copied()
-        .context(NoBtReadingSnafu)?;
-
-    let multimeter_milliamps = match reading.mode {
-        Mode::DcMilliAmpere => reading.value(),
-        mode => return Err(ReformCapError::WrongReadingMode { mode }),
+    psu: &mut impl PowerSupply,
+    sample: &CurrentSample,
+    curr_voltage: f64,
+    current_limit_milliamps: f64,
+    charging: &ChargingPeriod,
+    last_flags: &mut MeterFlags,
+) -> Result<Option<f64>, ReformCapError> {
+    let flags = sample.value.flags;
+    if flags.hold != last_flags.hold {
+        if flags.hold {
+            eprintln!("Warning: multimeter switched to hold mode, holding voltage until released");
+        } else {
+            println!("Multimeter hold mode released, resuming");
+        }
+    }
+    if flags.relative && !last_flags.relative {
+        eprintln!("Warning: multimeter switched to relative mode, readings are offset");
+    }
+    if flags.low_battery && !last_flags.low_battery {
+        eprintln!("Warning: multimeter battery is low");
+    }
+    *last_flags = flags;
+
+    if flags.hold {
+        return Ok(None);
+    }
+
+    match sample.value.milliamps() {
+        Measurement::Value(milliamps) => Ok(Some(milliamps)),
+        Measurement::Overload => {
+            let output = psu.voltage_and_current().await?;
+            println!(
+                "Reform current: OL (PSU: {}) at {:.2}V",
+                si(output.value.current, "A"),
+                output.value.voltage
+            );
+            ensure!(
+                output.value.current * 1000.0 < current_limit_milliamps,
+                CapCurrentLimitExceededSnafu
+            );
+            ensure!(
+                output.received_at.is_some_and(|at| charging.contains(at)),
+                MeterOverloadedSnafu
+            );
+            Ok(None)
+        }
+        Measurement::Blank => {
+            println!("Reform current: no value at {curr_voltage:.2}V");
+            Ok(None)
+        }
+    }
+}
+
+/// Time after a voltage increase during which the capacitor charges, so that the PSU may be in
+/// constant current mode and the multimeter may be overloaded.
+struct ChargingPeriod {
+    last_voltage_increase: Instant,
+    duration: Duration,
+}
+
+impl ChargingPeriod {
+    fn time_since_increase(&self, instant: Instant) -> Duration {
+        instant.duration_since(self.last_voltage_increase)
+    }
+
+    fn contains(&self, instant: Instant) -> bool {
+        self.time_since_increase(instant) < self.duration
+    }
+}
+
+/// Default for `--cc-grace-period`: a multiple of the time a voltage step takes to charge the
+/// capacitor at the PSU current limit `psu_current` in A, or [`CC_GRACE_PERIOD`] if the
+/// capacitance in µF is unknown.
+fn default_cc_grace_period(
+    capacitance: Option<f64>,
+    voltage_step: f64,
+    psu_current: f64,
+) -> Duration {
+    let Some(capacitance) = capacitance else {
+        return CC_GRACE_PERIOD;
     };
+    let charge_time = capacitance / 1e6 * voltage_step / psu_current;
+    Duration::try_from_secs_f64(charge_time * CC_GRACE_CHARGE_TIME_FACTOR)
+        .unwrap_or_default()
+        .max(CC_GRACE_PERIOD)
+}
+
+/// Waits for the next multimeter sample. While no samples arrive (e.g. while the multimeter is
+/// reconnecting), holds the voltage, or lowers it by `lower_step` every
+/// [`METER_DROPOUT_TIMEOUT`]. Only returns samples accepted by `monitor`, and fails once it
+/// considers the samples stale. Returns `None` if cancelled.
+async fn next_sample_or_hold(
+    psu: &mut impl PowerSupply,
+    meter: &mut impl CurrentMeter,
+    cancel: &CancellationToken,
+    monitor: &mut SampleMonitor,
+    curr_voltage: &mut f64,
+    lower_step: Option<f64>,
+    charging: &ChargingPeriod,
+) -> Result<Option<CurrentSample>, ReformCapError> {
+    loop {
+        let timeout = METER_DROPOUT_TIMEOUT.min(monitor.max_age);
+        let sample = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            res = tokio::time::timeout(timeout, meter.next_sample()) => res,
+        };
+        match sample {
+            Ok(sample) => {
+                let sample = sample?;
+                if monitor.check_sample(&sample)? {
+                    return Ok(Some(sample));
+                }
+                monitor.check_age()?;
+                continue;
+            }
+            Err(_) => {
+                monitor.check_age()?;
+                monitor.dropout();
+            }
+        }
 
-    Ok(multimeter_milliamps)
+        match lower_step {
+            Some(step) if *curr_voltage > 0.0 => {
+                *curr_voltage = (*curr_voltage - step).max(0.0);
+                psu.set_voltage(*curr_voltage).await?;
+                println!(
+                    "No multimeter reading, lowered voltage to {:.2}V",
+                    curr_voltage
+                );
+            }
+            _ => println!(
+                "No multimeter reading, holding voltage at {:.2}V",
+                curr_voltage
+            ),
+        }
+        check_psu_status(psu, charging).await?;
+    }
 }
 
-fn print_measurement(rated_voltage: f64, capacitance: Option<f64>, voltage: f64, milliamps: f64) {
+/// Checks that multimeter samples are fresh and arrive often enough, independent of how the
+/// multimeter backend detects a lost connection.
+struct SampleMonitor {
+    max_age: Duration,
+    /// Longest time the reading may stay the same, or `None` to allow any steady reading
+    max_unchanged: Option<Duration>,
+    /// Minimum sample rate in 1/s
+    min_rate: f64,
+    last_sequence: Option<u64>,
+    /// Time at which the last new sample with a current was received, or monitoring started
+    last_received: Instant,
+    last_value: Option<Current>,
+    /// Time at which the reading last changed, or monitoring started
+    last_changed: Instant,
+    /// Receive times of the last samples since the last dropout
+    recent: VecDeque<Instant>,
+}
+
+impl SampleMonitor {
+    fn new(max_age: Duration, max_unchanged: Option<Duration>, min_rate: f64) -> Self {
+        Self {
+            max_age,
+            max_unchanged,
+            min_rate,
+            last_sequence: None,
+            last_received: Instant::now(),
+            last_value: None,
+            last_changed: Instant::now(),
+            recent: VecDeque::with_capacity(SAMPLE_RATE_WINDOW),
+        }
+    }
+
+    /// Checks the age of a sample, how long the multimeter has been held or unchanged, and the
+    /// sample rate. Returns `false` for a repeated sample, which must not be used.
+    fn check_sample(&mut self, sample: &CurrentSample) -> Result<bool, ReformCapError> {
+        if self
+            .last_sequence
+            .is_some_and(|last_sequence| sample.sequence <= last_sequence)
+        {
+            return Ok(false);
+        }
+
+        let received_at = sample.received_at.context(MeterSampleNotReceivedSnafu)?;
+        let age = received_at.elapsed();
+        ensure!(age <= self.max_age, MeterSamplesStaleSnafu { age });
+
+        self.last_sequence = Some(sample.sequence);
+        // A held or blank display (e.g. an unknown mode) says nothing about the current.
+        if !sample.value.flags.hold && sample.value.amperes != Measurement::Blank {
+            self.last_received = received_at;
+        }
+        if self.last_value != Some(sample.value) {
+            self.last_value = Some(sample.value);
+            self.last_changed = received_at;
+        }
+        self.check_age()?;
+
+        if self.recent.len() == SAMPLE_RATE_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(received_at);
+        if let (Some(first), Some(last), SAMPLE_RATE_WINDOW) =
+            (self.recent.front(), self.recent.back(), self.recent.len())
+        {
+            let rate = (SAMPLE_RATE_WINDOW - 1) as f64 / last.duration_since(*first).as_secs_f64();
+            ensure!(
+                rate >= self.min_rate,
+                MeterSampleRateTooLowSnafu {
+                    rate,
+                    min_rate: self.min_rate
+                }
+            );
+        }
+
+        Ok(true)
+    }
+
+    /// Fails if no new sample was received for longer than the maximum age, or the reading
+    /// stayed the same for too long.
+    fn check_age(&self) -> Result<(), ReformCapError> {
+        let age = self.last_received.elapsed();
+        ensure!(age <= self.max_age, MeterSamplesStaleSnafu { age });
+
+        if let Some(max_unchanged) = self.max_unchanged {
+            let duration = self.last_changed.elapsed();
+            ensure!(
+                duration <= max_unchanged,
+                MeterReadingUnchangedSnafu { duration }
+            );
+        }
+        Ok(())
+    }
+
+    /// Restarts measuring the sample rate, as it only applies while samples are arriving.
+    fn dropout(&mut self) {
+        self.recent.clear();
+    }
+}
+
+/// Fails if the PSU reports a tripped protection, a disabled output, or constant current mode
+/// outside of the charging period after a voltage increase.
+async fn check_psu_status(
+    psu: &mut impl PowerSupply,
+    charging: &ChargingPeriod,
+) -> Result<(), ReformCapError> {
+    let status = psu.status().await?;
+    let is_charging = status.received_at.is_some_and(|at| charging.contains(at));
+    let status = status.value;
+
+    ensure!(
+        status.protection == Protection::None,
+        PsuProtectionTrippedSnafu {
+            protection: status.protection
+        }
+    );
+    ensure!(status.output_enabled, PsuOutputDisabledSnafu);
+    ensure!(
+        status.regulation_mode == RegulationMode::ConstantVoltage || is_charging,
+        PsuConstantCurrentSnafu
+    );
+
+    Ok(())
+}
+
+/// Prints a measurement, prefixed with the time since the first measurement in seconds.
+fn print_measurement(
+    since_start: Duration,
+    rated_voltage: f64,
+    capacitance: Option<f64>,
+    voltage: f64,
+    milliamps: f64,
+) {
+    let current = si(milliamps / 1000.0, "A");
+    let time = since_start.as_secs_f64();
     if let Some(capacitance) = capacitance {
         println!(
-            "Reform current: {milliamps:.3}mA ({:.5} CV) at {voltage:.2}V",
+            "[{time:7.1}s] Reform current: {current} ({:.5} CV) at {voltage:.2}V",
             milliamps * 1000.0 / (rated_voltage * capacitance),
         );
     } else {
-        println!("Reform current: {milliamps:.3}mA at {voltage:.2}V",);
+        println!("[{time:7.1}s] Reform current: {current} at {voltage:.2}V",);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    async fn reform_simulated(args: &[&str]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let config = Config::from_args(&["reform"], args).unwrap();
+        let (psu, meter) = new_sim_pair(&config);
+        run_reform(psu, meter, CancellationToken::new(), config).await
+    }
+
+    fn reform_cap_error(res: Result<(), Box<dyn Error + Send + Sync>>) -> ReformCapError {
+        *res.unwrap_err().downcast::<ReformCapError>().unwrap()
+    }
+
+    /// Interval between two samples of a [`StubMeter`] that keeps up
+    const STUB_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// A sample of a [`StubMeter`], returned `delay` after the previous one, and received `age`
+    /// before it is returned.
+    struct StubSample {
+        delay: Duration,
+        age: Duration,
+        sequence: u64,
+        current: Current,
+    }
+
+    fn stub_sample(sequence: u64, milliamps: f64) -> StubSample {
+        StubSample {
+            delay: STUB_INTERVAL,
+            age: Duration::ZERO,
+            sequence,
+            current: Current {
+                amperes: Measurement::Value(milliamps / 1000.0),
+                flags: MeterFlags::default(),
+            },
+        }
+    }
+
+    /// Multimeter returning scripted samples. Never returns once the script is used up.
+    struct StubMeter {
+        samples: Box<dyn Iterator<Item = StubSample> + Send>,
+    }
+
+    impl StubMeter {
+        fn new(samples: impl IntoIterator<Item = StubSample, IntoIter: Send + 'static>) -> Self {
+            Self {
+                samples: Box::new(samples.into_iter()),
+            }
+        }
+    }
+
+    impl CurrentMeter for StubMeter {
+        async fn next_sample(&mut self) -> Result<CurrentSample, MeterError> {
+            let Some(sample) = self.samples.next() else {
+                return std::future::pending().await;
+            };
+            tokio::time::sleep(sample.delay).await;
+            Ok(CurrentSample {
+                value: sample.current,
+                sequence: sample.sequence,
+                received_at: Some(Instant::now() - sample.age),
+                timestamp: std::time::SystemTime::now(),
+            })
+        }
+    }
+
+    /// Reforms a simulated 10V capacitor with little leakage, measuring the current with `meter`
+    /// instead of the simulated multimeter, and returns why reforming failed.
+    async fn reform_with_meter(args: &[&str], meter: StubMeter) -> ReformCapError {
+        let args = [&["sim", "10", "--sim-leakage", "1"], args].concat();
+        let config = Config::from_args(&["reform"], &args).unwrap();
+        let (psu, _) = new_sim_pair(&config);
+        reform_cap_error(run_reform(psu, meter, CancellationToken::new(), config).await)
+    }
+
+    /// Simulated PSU that fails to switch off its output, and records the protection settings.
+    struct StuckOutputPsu {
+        psu: sim::SimPsu,
+        protection: Arc<Mutex<Vec<(f64, f64)>>>,
+    }
+
+    impl PowerSupply for StuckOutputPsu {
+        fn limits(&self) -> psu::PsuLimits {
+            self.psu.limits()
+        }
+
+        async fn set_output(&mut self, enable: bool) -> Result<(), PowerSupplyError> {
+            if !enable {
+                return Err(PowerSupplyError::Backend {
+                    source: "output stuck".into(),
+                });
+            }
+            self.psu.set_output(enable).await
+        }
+
+        async fn set_voltage(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+            self.psu.set_voltage(voltage).await
+        }
+
+        async fn set_current(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+            self.psu.set_current(current).await
+        }
+
+        async fn set_voltage_protection(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+            self.psu.set_voltage_protection(voltage).await?;
+            let protection = self.psu.voltage_and_current_protection().await?;
+            self.protection.lock().unwrap().push(protection);
+            Ok(())
+        }
+
+        async fn set_current_protection(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+            self.psu.set_current_protection(current).await?;
+            let protection = self.psu.voltage_and_current_protection().await?;
+            self.protection.lock().unwrap().push(protection);
+            Ok(())
+        }
+
+        async fn voltage_and_current(
+            &mut self,
+        ) -> Result<sample::Sample<psu::PsuOutput>, PowerSupplyError> {
+            self.psu.voltage_and_current().await
+        }
+
+        async fn status(&mut self) -> Result<sample::Sample<psu::PsuStatus>, PowerSupplyError> {
+            self.psu.status().await
+        }
+
+        async fn voltage_and_current_protection(&mut self) -> Result<(f64, f64), PowerSupplyError> {
+            self.psu.voltage_and_current_protection().await
+        }
+
+        async fn disconnect(&mut self) -> Result<(), PowerSupplyError> {
+            self.psu.disconnect().await
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restores_protection_after_errors() {
+        // The current limit is below the resolution of the simulated PSU.
+        let config =
+            Config::from_args(&["reform"], &["sim", "10", "--current-limit", "0.05"]).unwrap();
+        let (psu, meter) = new_sim_pair(&config);
+        let protection = Arc::new(Mutex::new(Vec::new()));
+        let psu = StuckOutputPsu {
+            psu,
+            protection: protection.clone(),
+        };
+
+        let err = reform_cap_error(run_reform(psu, meter, CancellationToken::new(), config).await);
+        assert!(
+            matches!(err, ReformCapError::PsuCurrentLimitUnrepresentable { .. }),
+            "{err:?}"
+        );
+        assert_eq!(protection.lock().unwrap().last(), Some(&(62.0, 6.2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reforms_with_replayed_capture() {
+        // 1.234mA DC for 40s, then 10µA DC
+        let records = (0..100)
+            .map(|i| owon::capture::CaptureRecord {
+                timestamp: Duration::from_millis(1_700_000_000_000 + i * 500),
+                uuid: "0000fff4-0000-1000-8000-00805f9b34fb".parse().unwrap(),
+                bytes: if i < 80 {
+                    vec![0x9b, 0xf0, 0x04, 0x00, 0xd2, 0x04]
+                } else {
+                    vec![0x9b, 0xf0, 0x04, 0x00, 0x0a, 0x00]
+                },
+            })
+            .collect();
+
+        let config =
+            Config::from_args(&["reform"], &["sim", "10", "--sim-leakage", "0.1"]).unwrap();
+        let (psu, _) = new_sim_pair(&config);
+        let cancel = CancellationToken::new();
+        let (reading_tx, reading_rx) = watch::channel(None);
+        let replay_task = owon::start_replay_task(cancel.clone(), reading_tx, records);
+        run_reform(
+            psu,
+            owon::Meter::new(reading_rx, None),
+            cancel.clone(),
+            config,
+        )
+        .await
+        .unwrap();
+
+        // Reforming completes with the first sample below the finish current.
+        assert!(!replay_task.is_finished());
+        cancel.cancel();
+        replay_task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reforms_simulated_capacitor() {
+        reform_simulated(&["sim", "10", "100", "--sim-forming-time", "10"])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_shorted_capacitor() {
+        let err = reform_cap_error(reform_simulated(&["sim", "10", "100", "--sim-short"]).await);
+        assert!(
+            matches!(
+                err,
+                ReformCapError::PsuConstantCurrent | ReformCapError::PsuProtectionTripped { .. }
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_breakdown() {
+        let err = reform_cap_error(
+            reform_simulated(&["sim", "10", "100", "--sim-breakdown-voltage", "5"]).await,
+        );
+        assert!(
+            matches!(
+                err,
+                ReformCapError::PsuConstantCurrent | ReformCapError::PsuProtectionTripped { .. }
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reforms_large_capacitor() {
+        let args = [
+            "sim",
+            "5",
+            "47000",
+            "--sim-leakage",
+            "1",
+            "--sim-forming-time",
+            "1",
+        ];
+        reform_simulated(&args).await.unwrap();
+
+        // A voltage step takes 2.6s to charge 47mF at 9mA.
+        let err = reform_cap_error(
+            reform_simulated(&[&args[..], &["--cc-grace-period", "2"]].concat()).await,
+        );
+        assert!(matches!(err, ReformCapError::PsuConstantCurrent), "{err:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_held_multimeter() {
+        let meter = StubMeter::new((0..).map(|sequence| {
+            let mut sample = stub_sample(sequence, 1.0);
+            sample.current.flags.hold = true;
+            sample
+        }));
+        let err = reform_with_meter(&["--max-sample-age", "10"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { age } if age > Duration::from_secs(10)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_blank_multimeter() {
+        let meter = StubMeter::new((0..).map(|sequence| StubSample {
+            current: Current {
+                amperes: Measurement::Blank,
+                flags: MeterFlags::default(),
+            },
+            ..stub_sample(sequence, 0.0)
+        }));
+        let err = reform_with_meter(&["--max-sample-age", "10"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { age } if age > Duration::from_secs(10)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_repeated_samples() {
+        let meter = StubMeter::new((0..).map(|_| stub_sample(0, 1.0)));
+        let err = reform_with_meter(&["--max-sample-age", "10"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { .. }),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_missing_samples() {
+        let meter = StubMeter::new((0..5).map(|sequence| stub_sample(sequence, 1.0)));
+        let err = reform_with_meter(&["--max-sample-age", "10"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { age } if age > Duration::from_secs(10)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_old_sample() {
+        let meter = StubMeter::new([StubSample {
+            age: Duration::from_secs(20),
+            ..stub_sample(0, 1.0)
+        }]);
+        let err = reform_with_meter(&["--max-sample-age", "10"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { age } if age >= Duration::from_secs(20)),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_slow_samples() {
+        // Slower than the minimum rate, but fast enough not to count as dropouts
+        let meter = StubMeter::new((0..).map(|sequence| StubSample {
+            delay: Duration::from_secs(2),
+            ..stub_sample(sequence, 1.0)
+        }));
+        let err = reform_with_meter(&[], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSampleRateTooLow { rate, .. } if rate == 0.5),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_on_unchanged_reading() {
+        let meter = StubMeter::new((0..).map(|sequence| stub_sample(sequence, 1.0)));
+        let err = reform_with_meter(&["--max-unchanged-time", "5"], meter).await;
+        assert!(
+            matches!(err, ReformCapError::MeterReadingUnchanged { duration } if duration > Duration::from_secs(5)),
+            "{err:?}"
+        );
+    }
+
+    /// Waits for samples from a multimeter that never sends any, with the PSU output at 5V.
+    /// Returns the error and the voltage afterwards.
+    async fn meter_dropout(lower_step: Option<f64>) -> (ReformCapError, f64) {
+        let config = Config::from_args(&["reform"], &["sim", "10", "--sim-leakage", "1"]).unwrap();
+        let (mut psu, _) = new_sim_pair(&config);
+        psu.set_voltage(5.0).await.unwrap();
+        psu.set_current(0.009).await.unwrap();
+        psu.set_output(true).await.unwrap();
+
+        let mut monitor = SampleMonitor::new(Duration::from_secs(10), None, 1.0);
+        let charging = ChargingPeriod {
+            last_voltage_increase: Instant::now(),
+            duration: CC_GRACE_PERIOD,
+        };
+        let mut voltage = 5.0;
+        let err = next_sample_or_hold(
+            &mut psu,
+            &mut StubMeter::new([]),
+            &CancellationToken::new(),
+            &mut monitor,
+            &mut voltage,
+            lower_step,
+            &charging,
+        )
+        .await
+        .unwrap_err();
+        (err, voltage)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn holds_voltage_on_meter_dropout() {
+        let (err, voltage) = meter_dropout(None).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { .. }),
+            "{err:?}"
+        );
+        assert_eq!(voltage, 5.0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lowers_voltage_on_meter_dropout() {
+        let (err, voltage) = meter_dropout(Some(0.5)).await;
+        assert!(
+            matches!(err, ReformCapError::MeterSamplesStale { .. }),
+            "{err:?}"
+        );
+        // Lowered after 3s, 6s and 9s, stale after 12s
+        assert_eq!(voltage, 3.5);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test(start_paused = true)]
+    async fn rejects_loaded_samples() {
+        let sample = sample::Sequencer::default().sample(stub_sample(0, 1.0).current);
+        let json = serde_json::to_value(sample).unwrap();
+        let loaded: CurrentSample = serde_json::from_value(json).unwrap();
+        assert_eq!(loaded.received_at, None);
+
+        let mut monitor = SampleMonitor::new(Duration::from_secs(10), None, 1.0);
+        let err = monitor.check_sample(&loaded).unwrap_err();
+        assert!(
+            matches!(err, ReformCapError::MeterSampleNotReceived),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn default_reconnect_budget_fits_max_sample_age() {
+        let config = Config::from_args(&["reform"], &["/dev/ttyUSB0", "25"]).unwrap();
+        let selector = owon::DeviceSelector {
+            adapter: None,
+            meter: None,
+            scan_timeout: Duration::from_secs(config.scan_timeout),
+            reconnect_attempts: config.meter_reconnect_attempts,
+        };
+        assert!(owon::reconnect_budget(&selector) <= config.max_sample_age);
+    }
+
+    #[test]
+    fn sample_rates() {
+        assert_eq!(parse_sample_rate("0"), Ok(0.0));
+        assert_eq!(parse_sample_rate("2.5"), Ok(2.5));
+        for value in ["-1", "nan", "inf", "fast"] {
+            assert!(parse_sample_rate(value).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn meter_aliases() {
+        let aliases = parse_meter_aliases(
+            "# bench meters\n\nleft = A6:C0:80:E3:85:9D\nright=A6:C0:80:E3:85:9E\n",
+        )
+        .unwrap();
+        let aliases: Vec<_> = aliases
+            .iter()
+            .map(|alias| (alias.alias.as_str(), alias.address.as_str()))
+            .collect();
+        assert_eq!(
+            aliases,
+            [
+                ("left", "A6:C0:80:E3:85:9D"),
+                ("right", "A6:C0:80:E3:85:9E")
+            ]
+        );
+
+        let err = parse_meter_aliases("left=A6:C0:80:E3:85:9D\nright\n").unwrap_err();
+        assert_eq!(err, "line 2: expected `alias=address`, got `right`");
     }
 }
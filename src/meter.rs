@@ -0,0 +1,70 @@
+use crate::sample::Sample;
+use snafu::Snafu;
+use std::future::Future;
+use tokio::sync::watch;
+
+/// A value shown by a meter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Measurement {
+    Value(f64),
+    /// The value is outside of the measurement range ("OL")
+    Overload,
+    /// The meter doesn't show a value
+    Blank,
+}
+
+impl Measurement {
+    pub fn map(self, f: impl FnOnce(f64) -> f64) -> Self {
+        match self {
+            Measurement::Value(value) => Measurement::Value(f(value)),
+            other => other,
+        }
+    }
+}
+
+/// Meter states that affect how far a sample can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeterFlags {
+    /// The display is frozen, the value is stale
+    pub hold: bool,
+    /// The value is relative to a reference value
+    pub relative: bool,
+    pub low_battery: bool,
+}
+
+/// A single current measurement taken by a [`CurrentMeter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Current {
+    /// Measured current in A
+    pub amperes: Measurement,
+    pub flags: MeterFlags,
+}
+
+pub type CurrentSample = Sample<Current>;
+
+impl Current {
+    pub fn milliamps(&self) -> Measurement {
+        self.amperes.map(|amperes| amperes * 1000.0)
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub))]
+pub enum MeterError {
+    #[snafu(context(false))]
+    ChannelClosed { source: watch::error::RecvError },
+    /// No reading available from the multimeter
+    NoReading,
+    /// The meter isn't measuring a current, `mode` describes what it measures instead
+    #[snafu(display("Wrong reading mode, got {mode}"))]
+    WrongReadingMode { mode: String },
+}
+
+/// A source of current measurements, e.g. a multimeter in series with the capacitor.
+pub trait CurrentMeter {
+    /// Waits for the next current sample.
+    fn next_sample(&mut self) -> impl Future<Output = Result<CurrentSample, MeterError>> + Send;
+}
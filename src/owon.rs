@@ -1,23 +1,77 @@
+pub mod capture;
+pub mod command;
 pub mod mode;
 pub mod reading;
 
+use crate::{
+    meter::{
+        Current, CurrentMeter, CurrentSample, Measurement, MeterError, MeterFlags, NoReadingSnafu,
+        WrongReadingModeSnafu,
+    },
+    sample::{Sample, Sequencer},
+};
 use btleplug::{
-    api::{Central, CentralEvent, Manager as _, Peripheral, ScanFilter, ValueNotification},
-    platform::Manager,
+    api::{
+        Central, CentralEvent, CharPropFlags, Manager as _, Peripheral as _, PeripheralProperties,
+        ScanFilter, ValueNotification, WriteType,
+    },
+    platform::{Adapter, Manager, Peripheral},
 };
-use futures_lite::{Stream, StreamExt};
+use capture::{CaptureRecord, CaptureWriter};
+use command::Command;
+use futures_lite::{stream::Boxed, Stream, StreamExt};
 use mode::Mode;
-use snafu::{ensure, Snafu};
-use std::{ops::ControlFlow, time::Duration};
+use snafu::{ensure, OptionExt, Snafu};
+use std::{
+    ops::ControlFlow,
+    time::{Duration, SystemTime},
+};
 use tokio::{sync::watch, task::JoinHandle, time};
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 const OW18E_SERVICE: Uuid = uuid::uuid!("0000fff0-0000-1000-8000-00805f9b34fb");
-// const OW18E_WRITE_CHARACTERISTIC: Uuid = uuid::uuid!("0000fff3-0000-1000-8000-00805f9b34fb");
+const OW18E_WRITE_CHARACTERISTIC: Uuid = uuid::uuid!("0000fff3-0000-1000-8000-00805f9b34fb");
 const OW18E_NOTIFY_CHARACTERISTIC: Uuid = uuid::uuid!("0000fff4-0000-1000-8000-00805f9b34fb");
 
 const TIMEOUT: Duration = Duration::from_secs(2);
+/// How long to wait for a command to show up in the readings
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+/// Upper bound for the number of ranges of a mode, see [`Meter::range_down`]
+const MAX_RANGES: usize = 10;
+/// Wait time between two reconnection attempts
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Longest time a reconnection attempt scans for the multimeter, so that the default number of
+/// attempts fits into the default maximum sample age of the reforming logic
+const RECONNECT_SCAN_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Notifications of the multimeter's notify characteristic
+type Notifications = Boxed<ValueNotification>;
+
+/// Selects the Bluetooth adapter and multimeter to use.
+#[derive(Debug, Clone)]
+pub struct DeviceSelector {
+    /// Name in the adapter description (e.g. `hci1`), or `None` for the first adapter
+    pub adapter: Option<String>,
+    /// Address or local name of the multimeter, or `None` for the first one found
+    pub meter: Option<String>,
+    /// How long to scan for a matching multimeter. Reconnection attempts scan for at most
+    /// [`RECONNECT_SCAN_TIMEOUT`].
+    pub scan_timeout: Duration,
+    /// How often to try reconnecting after the multimeter connection was lost
+    pub reconnect_attempts: u32,
+}
+
+impl DeviceSelector {
+    fn matches(&self, properties: &PeripheralProperties) -> bool {
+        let Some(meter) = &self.meter else {
+            return true;
+        };
+
+        properties.address.to_string().eq_ignore_ascii_case(meter)
+            || properties.local_name.as_deref() == Some(meter.as_str())
+    }
+}
 
 #[derive(Debug, Snafu)]
 pub enum StartBtMessageStreamError {
@@ -25,26 +79,231 @@ pub enum StartBtMessageStreamError {
     Btle {
         source: btleplug::Error,
     },
+    /// No Bluetooth adapter found
+    NoAdapter,
+    #[snafu(display("No Bluetooth adapter matching `{adapter}` found"))]
+    AdapterNotFound {
+        adapter: String,
+    },
+    #[snafu(display("No matching multimeter found within {timeout:?}"))]
+    MeterNotFound {
+        timeout: Duration,
+    },
+    #[snafu(display("Could not reconnect to the multimeter after {attempts} attempts"))]
+    ReconnectFailed {
+        attempts: u32,
+    },
     InitialNotificationDidNotArrive,
-    /// The multimeter is in the wrong mode. It must be set to DC milliampere mode.
+    /// The multimeter disconnected while subscribing to its readings
+    NotConnected,
+    /// The multimeter has no OW18E service
+    NoService,
+    /// The multimeter has no notify characteristic
+    NoNotifyCharacteristic,
+    /// The multimeter is in the wrong mode. It must be set to one of the DC current modes.
     MultimeterInWrongMode,
 }
 
 pub async fn start_bt_message_stream_task(
     cancel: CancellationToken,
-    reading_tx: watch::Sender<Option<reading::Reading>>,
-) -> Result<JoinHandle<Result<(), btleplug::Error>>, StartBtMessageStreamError> {
-    let manager = Manager::new().await?;
-    let adapter_list = manager.adapters().await?;
-    let Some(adapter) = adapter_list.into_iter().next() else {
-        panic!("No Bluetooth adapter found");
-    };
+    reading_tx: watch::Sender<Option<Sample<reading::Reading>>>,
+    selector: &DeviceSelector,
+    mut capture: Option<CaptureWriter>,
+) -> Result<
+    (
+        JoinHandle<Result<(), StartBtMessageStreamError>>,
+        MeterControl,
+    ),
+    StartBtMessageStreamError,
+> {
+    let adapter = select_adapter(selector.adapter.as_deref()).await?;
 
     println!(
         "Using adapter {}, searching for OW18E_SERVICE device...",
         adapter.adapter_info().await?
     );
 
+    let device = find_meter(&adapter, selector).await?;
+    let device_id = device.id();
+
+    let mut notifications = subscribe(&device).await?;
+
+    println!("Waiting for initial reading...");
+    let ControlFlow::Continue(initial_reading) =
+        read_notification(&cancel, &mut notifications, &mut capture).await?
+    else {
+        return InitialNotificationDidNotArriveSnafu.fail();
+    };
+
+    ensure!(
+        initial_reading.amperes().is_some(),
+        MultimeterInWrongModeSnafu
+    );
+
+    println!("Initial reading valid, starting message stream.");
+
+    // Reconnect to the same multimeter, even if the user didn't select a specific one.
+    let selector = DeviceSelector {
+        meter: Some(device.address().to_string()),
+        scan_timeout: selector.scan_timeout.min(RECONNECT_SCAN_TIMEOUT),
+        ..selector.clone()
+    };
+
+    let (device_tx, device_rx) = watch::channel(device.clone());
+
+    let bt_task = tokio::spawn(async move {
+        let mut device = device;
+        let mut sequencer = Sequencer::default();
+        loop {
+            let flow = read_notification(&cancel, &mut notifications, &mut capture).await?;
+            match flow {
+                ControlFlow::Continue(reading) => {
+                    if reading_tx.send(Some(sequencer.sample(reading))).is_err() {
+                        break;
+                    }
+                }
+                ControlFlow::Break(StreamEnd::Cancelled) => break,
+                ControlFlow::Break(StreamEnd::Lost) => {
+                    let Some(reconnected) =
+                        reconnect(&adapter, &selector, &device, &cancel).await?
+                    else {
+                        break;
+                    };
+                    (device, notifications) = reconnected;
+                    device_tx.send_replace(device.clone());
+                }
+            }
+        }
+
+        let is_connected = device.is_connected().await?;
+        if is_connected {
+            println!("Disconnecting from peripheral {device_id}...");
+            device.disconnect().await?;
+        }
+
+        Ok(())
+    });
+
+    Ok((bt_task, MeterControl { device_rx }))
+}
+
+/// Feeds the readings of a capture file into `reading_tx` as if they came from a multimeter,
+/// keeping the recorded timing. Runs in real time, unless the Tokio clock is paused.
+pub fn start_replay_task(
+    cancel: CancellationToken,
+    reading_tx: watch::Sender<Option<Sample<reading::Reading>>>,
+    records: Vec<CaptureRecord>,
+) -> JoinHandle<Result<(), StartBtMessageStreamError>> {
+    tokio::spawn(async move {
+        let start = time::Instant::now();
+        let first_timestamp = records.first().map(|record| record.timestamp);
+        let mut sequencer = Sequencer::default();
+
+        for record in records {
+            if record.uuid != OW18E_NOTIFY_CHARACTERISTIC {
+                continue;
+            }
+
+            let offset = record
+                .timestamp
+                .saturating_sub(first_timestamp.unwrap_or_default());
+            tokio::select! {
+                _ = cancel.cancelled() => return Ok(()),
+                _ = time::sleep_until(start + offset) => {}
+            }
+
+            match reading::parse(&record.bytes) {
+                Some(reading) => {
+                    let sample = Sample {
+                        timestamp: SystemTime::UNIX_EPOCH + record.timestamp,
+                        ..sequencer.sample(reading)
+                    };
+                    if reading_tx.send(Some(sample)).is_err() {
+                        return Ok(());
+                    }
+                }
+                None => eprintln!("Invalid message in capture. Raw: {:#?}", record.bytes),
+            }
+        }
+
+        println!("Replay finished");
+        Ok(())
+    })
+}
+
+/// Longest time from the last reading until all reconnection attempts of `selector` have scanned
+/// for the multimeter. Connecting to it takes additional time.
+pub fn reconnect_budget(selector: &DeviceSelector) -> Duration {
+    let attempt = selector.scan_timeout.min(RECONNECT_SCAN_TIMEOUT) + RECONNECT_DELAY;
+    TIMEOUT + attempt * selector.reconnect_attempts
+}
+
+/// Tries to find and subscribe to the multimeter again, until the reconnection attempts are used
+/// up. Returns `None` if cancelled.
+async fn reconnect(
+    adapter: &Adapter,
+    selector: &DeviceSelector,
+    device: &Peripheral,
+    cancel: &CancellationToken,
+) -> Result<Option<(Peripheral, Notifications)>, StartBtMessageStreamError> {
+    let _ = device.disconnect().await;
+
+    for attempt in 1..=selector.reconnect_attempts {
+        println!(
+            "Multimeter connection lost, reconnecting (attempt {attempt}/{})...",
+            selector.reconnect_attempts
+        );
+
+        let res = tokio::select! {
+            _ = cancel.cancelled() => {
+                // Cancelling may have interrupted a scan.
+                let _ = adapter.stop_scan().await;
+                return Ok(None);
+            }
+            res = async {
+                let device = find_meter(adapter, selector).await?;
+                let notifications = subscribe(&device).await?;
+                Ok::<_, StartBtMessageStreamError>((device, notifications))
+            } => res,
+        };
+
+        match res {
+            Ok(reconnected) => {
+                println!("Multimeter reconnected");
+                return Ok(Some(reconnected));
+            }
+            Err(e) => eprintln!("Reconnecting failed: {e}"),
+        }
+
+        time::sleep(RECONNECT_DELAY).await;
+    }
+
+    ReconnectFailedSnafu {
+        attempts: selector.reconnect_attempts,
+    }
+    .fail()
+}
+
+/// A multimeter found by [`scan`].
+#[derive(Debug)]
+pub struct ScannedMeter {
+    pub device: Peripheral,
+    pub properties: PeripheralProperties,
+    pub is_connected: bool,
+}
+
+/// Scans for multimeters for the given duration. Only reports multimeters seen during the scan,
+/// not ones the Bluetooth stack remembers from earlier.
+pub async fn scan(
+    adapter: Option<&str>,
+    duration: Duration,
+) -> Result<Vec<ScannedMeter>, StartBtMessageStreamError> {
+    let adapter = select_adapter(adapter).await?;
+    println!(
+        "Using adapter {}, scanning for {duration:?}...",
+        adapter.adapter_info().await?
+    );
+
     let mut events = adapter.events().await?;
     adapter
         .start_scan(ScanFilter {
@@ -52,25 +311,77 @@ pub async fn start_bt_message_stream_task(
         })
         .await?;
 
-    let device_id = loop {
-        let Some(next) = events.next().await else {
-            panic!("Event stream ended without finding any devices");
+    let mut seen = Vec::new();
+    let deadline = time::Instant::now() + duration;
+    while let Ok(Some(event)) = time::timeout_at(deadline, events.next()).await {
+        if let CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id) = event {
+            if !seen.contains(&id) {
+                seen.push(id);
+            }
+        }
+    }
+    adapter.stop_scan().await?;
+
+    let mut meters = Vec::new();
+    for id in seen {
+        let device = adapter.peripheral(&id).await?;
+        let Some(properties) = device.properties().await? else {
+            continue;
         };
+        if !properties.services.contains(&OW18E_SERVICE) {
+            continue;
+        }
+
+        meters.push(ScannedMeter {
+            is_connected: device.is_connected().await?,
+            device,
+            properties,
+        });
+    }
+
+    Ok(meters)
+}
 
-        if let CentralEvent::DeviceDiscovered(id) = next {
-            println!("Found device ({id:?})");
-            break id;
+/// Collects up to `count` readings from a scanned multimeter. Disconnects afterwards, also on
+/// errors, unless the multimeter was already connected before.
+pub async fn sample_readings(
+    meter: &ScannedMeter,
+    count: usize,
+) -> Result<Vec<reading::Reading>, StartBtMessageStreamError> {
+    let readings = read_readings(&meter.device, count).await;
+
+    if !meter.is_connected {
+        let disconnected = meter.device.disconnect().await;
+        return readings.and_then(|readings| Ok(disconnected.map(|()| readings)?));
+    }
+
+    readings
+}
+
+async fn read_readings(
+    device: &Peripheral,
+    count: usize,
+) -> Result<Vec<reading::Reading>, StartBtMessageStreamError> {
+    let cancel = CancellationToken::new();
+    let mut notifications = subscribe(device).await?;
+
+    let mut readings = Vec::with_capacity(count);
+    while readings.len() < count {
+        match read_notification(&cancel, &mut notifications, &mut None).await? {
+            ControlFlow::Continue(reading) => readings.push(reading),
+            ControlFlow::Break(_) => break,
         }
-    };
+    }
 
-    drop(events);
-    let device = adapter.peripheral(&device_id).await?;
+    Ok(readings)
+}
 
+/// Connects to the multimeter if necessary, and subscribes to its readings.
+async fn subscribe(device: &Peripheral) -> Result<Notifications, StartBtMessageStreamError> {
     let properties = device.properties().await?;
     let is_connected = device.is_connected().await?;
     let local_name = properties
-        .unwrap()
-        .local_name
+        .and_then(|properties| properties.local_name)
         .unwrap_or(String::from("[unnamed]"));
 
     if is_connected {
@@ -80,91 +391,429 @@ pub async fn start_bt_message_stream_task(
         device.connect().await?;
     }
 
-    let is_connected = device.is_connected().await?;
-    assert!(is_connected);
+    ensure!(device.is_connected().await?, NotConnectedSnafu);
 
     device.discover_services().await?;
     let service = device
         .services()
         .into_iter()
         .find(|svc| svc.uuid == OW18E_SERVICE)
-        .unwrap();
+        .context(NoServiceSnafu)?;
 
     let notify_characteristic = service
         .characteristics
         .iter()
         .find(|c| c.uuid == OW18E_NOTIFY_CHARACTERISTIC)
-        .expect("Could not find notify characteristic");
+        .context(NoNotifyCharacteristicSnafu)?;
 
-    let mut notifications = device
+    let notifications = device
         .notifications()
         .await?
-        .filter(|n| n.uuid == OW18E_NOTIFY_CHARACTERISTIC);
+        .filter(|n| n.uuid == OW18E_NOTIFY_CHARACTERISTIC)
+        .boxed();
 
     device.subscribe(notify_characteristic).await?;
 
-    println!("Waiting for initial reading...");
-    let ControlFlow::Continue(initial_reading) =
-        read_notification(&cancel, &mut notifications).await?
-    else {
-        return InitialNotificationDidNotArriveSnafu.fail();
+    Ok(notifications)
+}
+
+async fn select_adapter(adapter: Option<&str>) -> Result<Adapter, StartBtMessageStreamError> {
+    let manager = Manager::new().await?;
+    let adapter_list = manager.adapters().await?;
+
+    let Some(name) = adapter else {
+        return adapter_list.into_iter().next().context(NoAdapterSnafu);
     };
 
-    ensure!(
-        initial_reading.mode == Mode::DcMilliAmpere,
-        MultimeterInWrongModeSnafu
-    );
+    for adapter in adapter_list {
+        if adapter_matches(&adapter.adapter_info().await?, name) {
+            return Ok(adapter);
+        }
+    }
 
-    println!("Initial reading valid, starting message stream.");
-    let bt_task: JoinHandle<Result<(), btleplug::Error>> = tokio::spawn(async move {
+    AdapterNotFoundSnafu { adapter: name }.fail()
+}
+
+/// Whether `name` is one of the words of an adapter description like
+/// `hci1 (usb:v1D6Bp0246d0540)`, so that `hci1` doesn't match `hci10`.
+fn adapter_matches(info: &str, name: &str) -> bool {
+    info.split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .any(|word| word == name)
+}
+
+/// Scans for the first multimeter matching the selector. Stops scanning before returning.
+async fn find_meter(
+    adapter: &Adapter,
+    selector: &DeviceSelector,
+) -> Result<Peripheral, StartBtMessageStreamError> {
+    let mut events = adapter.events().await?;
+    adapter
+        .start_scan(ScanFilter {
+            services: vec![OW18E_SERVICE],
+        })
+        .await?;
+
+    let res = wait_for_meter(adapter, selector, &mut events).await;
+    // BlueZ rejects starting another scan while one is running, e.g. when reconnecting.
+    let stopped = adapter.stop_scan().await;
+    let device = res?;
+    stopped?;
+    Ok(device)
+}
+
+/// Waits for a scan to find the first multimeter matching the selector.
+async fn wait_for_meter(
+    adapter: &Adapter,
+    selector: &DeviceSelector,
+    events: &mut (impl Stream<Item = CentralEvent> + Unpin),
+) -> Result<Peripheral, StartBtMessageStreamError> {
+    let deadline = time::Instant::now() + selector.scan_timeout;
+    loop {
+        let Ok(Some(next)) = time::timeout_at(deadline, events.next()).await else {
+            return MeterNotFoundSnafu {
+                timeout: selector.scan_timeout,
+            }
+            .fail();
+        };
+
+        // The local name may only arrive with a later update.
+        let (CentralEvent::DeviceDiscovered(id) | CentralEvent::DeviceUpdated(id)) = next else {
+            continue;
+        };
+
+        let device = adapter.peripheral(&id).await?;
+        let Some(properties) = device.properties().await? else {
+            continue;
+        };
+
+        if selector.matches(&properties) {
+            println!("Found device {} ({id:?})", properties.address);
+            return Ok(device);
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum MeterControlError {
+    #[snafu(context(false))]
+    ControlBtle { source: btleplug::Error },
+    #[snafu(context(false))]
+    ControlChannelClosed { source: watch::error::RecvError },
+    /// The multimeter has no write characteristic
+    NoWriteCharacteristic,
+    /// The multimeter can't be controlled, e.g. because readings are replayed from a capture
+    NoControl,
+    #[snafu(display("Multimeter did not apply command {command:?}"))]
+    CommandNotApplied { command: Command },
+    #[snafu(display("Command {command:?} has no button to press"))]
+    NoButton { command: Command },
+    #[snafu(display("Multimeter ranges did not wrap around within {MAX_RANGES} range presses"))]
+    RangesDidNotWrap,
+}
+
+/// Sends commands to the multimeter, see [`Command`].
+///
+/// Follows the multimeter across reconnects of the message stream task.
+#[derive(Debug, Clone)]
+pub struct MeterControl {
+    device_rx: watch::Receiver<Peripheral>,
+}
+
+impl MeterControl {
+    pub async fn send(&self, command: Command) -> Result<(), MeterControlError> {
+        let device = self.device_rx.borrow().clone();
+        let bytes = command.bytes().context(NoButtonSnafu { command })?;
+        let characteristic = device
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == OW18E_WRITE_CHARACTERISTIC)
+            .context(NoWriteCharacteristicSnafu)?;
+
+        let write_type = if characteristic
+            .properties
+            .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+
+        device.write(&characteristic, &bytes, write_type).await?;
+        Ok(())
+    }
+}
+
+/// [`CurrentMeter`] backed by the reading stream of [`start_bt_message_stream_task`].
+pub struct Meter {
+    reading_rx: watch::Receiver<Option<Sample<reading::Reading>>>,
+    control: Option<MeterControl>,
+    /// Mode of the last reading, to report range changes
+    last_mode: Option<Mode>,
+    /// Sequence number of the last reading, to report skipped readings
+    last_sequence: Option<u64>,
+}
+
+impl Meter {
+    pub fn new(
+        reading_rx: watch::Receiver<Option<Sample<reading::Reading>>>,
+        control: Option<MeterControl>,
+    ) -> Self {
+        Self {
+            reading_rx,
+            control,
+            last_mode: None,
+            last_sequence: None,
+        }
+    }
+
+    /// Releases hold mode if active, and optionally switches from autoranging to the current
+    /// range, so that the range can't change during reforming.
+    pub async fn prepare(&mut self, lock_range: bool) -> Result<(), MeterControlError> {
+        let reading = self.wait_for_reading(|_| true).await?;
+
+        if reading.hold {
+            println!("Multimeter is in hold mode, releasing it...");
+            self.apply(Command::Hold, |reading| !reading.hold).await?;
+        }
+        if lock_range && reading.autoranging {
+            println!("Locking multimeter range...");
+            self.apply(Command::Range, |reading| !reading.autoranging)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends `command`, and waits for the next reading, so that consecutive commands aren't sent
+    /// faster than the multimeter applies them.
+    pub async fn send(&mut self, command: Command) -> Result<(), MeterControlError> {
+        match command {
+            Command::RangeDown => self.range_down().await,
+            command => {
+                self.apply(command, |_| true).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Switches to manual ranging if needed, and steps to the next lower range, wrapping around
+    /// to the highest one.
+    ///
+    /// The OW18E can only step up, so this presses Range until the ranges wrap around to the
+    /// starting one, to learn the range below it, and then presses Range until it is reached.
+    pub async fn range_down(&mut self) -> Result<(), MeterControlError> {
+        let reading = self.wait_for_reading(|_| true).await?;
+        let start = if reading.autoranging {
+            self.apply(Command::Range, |reading| !reading.autoranging)
+                .await?
+                .range()
+        } else {
+            reading.range()
+        };
+
+        let mut ranges = vec![start];
         loop {
-            let flow = read_notification(&cancel, &mut notifications).await?;
-            match flow {
-                ControlFlow::Continue(reading) => {
-                    if reading_tx.send(Some(reading)).is_err() {
-                        break;
-                    }
+            let last = ranges[ranges.len() - 1];
+            let range = self
+                .apply(Command::Range, |reading| reading.range() != last)
+                .await?
+                .range();
+            if range == start {
+                break;
+            }
+            ensure!(ranges.len() < MAX_RANGES, RangesDidNotWrapSnafu);
+            ranges.push(range);
+        }
+
+        for &range in &ranges[1..] {
+            self.apply(Command::Range, |reading| reading.range() == range)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends `command`, and waits until a reading shows that it was applied.
+    pub async fn apply(
+        &mut self,
+        command: Command,
+        applied: impl Fn(&reading::Reading) -> bool,
+    ) -> Result<reading::Reading, MeterControlError> {
+        let control = self.control.as_ref().context(NoControlSnafu)?;
+        control.send(command).await?;
+        let reading = time::timeout(COMMAND_TIMEOUT, self.wait_for_reading(applied))
+            .await
+            .ok()
+            .context(CommandNotAppliedSnafu { command })??;
+        Ok(reading)
+    }
+
+    async fn wait_for_reading(
+        &mut self,
+        predicate: impl Fn(&reading::Reading) -> bool,
+    ) -> Result<reading::Reading, MeterControlError> {
+        loop {
+            self.reading_rx.changed().await?;
+            if let Some(sample) = *self.reading_rx.borrow_and_update() {
+                if predicate(&sample.value) {
+                    return Ok(sample.value);
                 }
-                ControlFlow::Break(()) => break,
             }
         }
+    }
+}
 
-        let is_connected = device.is_connected().await?;
-        if is_connected {
-            println!("Disconnecting from peripheral {device_id}...");
-            device
-                .disconnect()
-                .await
-                .expect("Error disconnecting from BLE peripheral");
+impl CurrentMeter for Meter {
+    async fn next_sample(&mut self) -> Result<CurrentSample, MeterError> {
+        self.reading_rx.changed().await?;
+        let sample = self
+            .reading_rx
+            .borrow_and_update()
+            .as_ref()
+            .copied()
+            .context(NoReadingSnafu)?;
+        let reading = sample.value;
+
+        if let Some(last_sequence) = self.last_sequence.replace(sample.sequence) {
+            let skipped = sample.sequence.saturating_sub(last_sequence + 1);
+            if skipped > 0 {
+                eprintln!("Warning: skipped {skipped} multimeter readings");
+            }
         }
 
-        Ok(())
-    });
+        let amperes = match reading.mode {
+            Mode::Unknown(raw) => {
+                eprintln!("Unknown multimeter mode {raw:#06X}, ignoring reading {reading:?}");
+                Measurement::Blank
+            }
+            mode => reading.amperes().context(WrongReadingModeSnafu {
+                mode: mode.as_str(),
+            })?,
+        };
+
+        if self.last_mode.replace(reading.mode) != Some(reading.mode) {
+            println!("Multimeter measuring in {}", reading.mode.as_str());
+        }
 
-    Ok(bt_task)
+        Ok(sample.map(|reading| Current {
+            amperes,
+            flags: MeterFlags {
+                hold: reading.hold,
+                relative: reading.relative,
+                low_battery: reading.low_battery,
+            },
+        }))
+    }
+}
+
+/// Why no more readings can be read from a notification stream.
+enum StreamEnd {
+    Cancelled,
+    /// The multimeter stopped sending valid notifications
+    Lost,
 }
 
+/// Reads and parses the next valid notification, writing every notification to `capture` first.
+/// Stops capturing if writing fails. Invalid notifications are skipped, the stream only counts as
+/// lost if no valid one arrives within [`TIMEOUT`].
 async fn read_notification(
     cancel: &CancellationToken,
     notifications: &mut (impl Stream<Item = ValueNotification> + Unpin),
-) -> Result<ControlFlow<(), reading::Reading>, btleplug::Error> {
-    tokio::select! {
-        _ = cancel.cancelled() => Ok(ControlFlow::Break(())),
-        _ = time::sleep(TIMEOUT) => {
-            eprintln!("Timeout waiting for notification");
-            Ok(ControlFlow::Break(()))
-        }
-        message = notifications.next() => {
-            match message {
-                Some(value) => match reading::parse(&value.value) {
-                    Some(reading) => Ok(ControlFlow::Continue(reading)),
-                    None => {
-                        eprintln!("Invalid message received. Raw: {:#?}", value.value);
-                        Ok(ControlFlow::Break(()))
-                    }
-                },
-                None => Ok(ControlFlow::Break(())),
+    capture: &mut Option<CaptureWriter>,
+) -> Result<ControlFlow<StreamEnd, reading::Reading>, btleplug::Error> {
+    let deadline = time::Instant::now() + TIMEOUT;
+    loop {
+        let message = tokio::select! {
+            _ = cancel.cancelled() => return Ok(ControlFlow::Break(StreamEnd::Cancelled)),
+            _ = time::sleep_until(deadline) => {
+                eprintln!("Timeout waiting for notification");
+                return Ok(ControlFlow::Break(StreamEnd::Lost));
+            }
+            message = notifications.next() => message,
+        };
+
+        if let (Some(value), Some(writer)) = (&message, capture.as_mut()) {
+            if let Err(e) = writer.write(value) {
+                eprintln!("Error writing capture file, capturing stopped: {e}");
+                *capture = None;
             }
         }
+
+        let Some(value) = message else {
+            return Ok(ControlFlow::Break(StreamEnd::Lost));
+        };
+        match reading::parse(&value.value) {
+            Some(reading) => return Ok(ControlFlow::Continue(reading)),
+            None => eprintln!(
+                "Invalid message received, skipping it. Raw: {:#?}",
+                value.value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn replays_with_recorded_timing() {
+        let record = |millis: u64, uuid: Uuid, bytes: &[u8]| CaptureRecord {
+            timestamp: Duration::from_millis(1_700_000_000_000 + millis),
+            uuid,
+            bytes: bytes.to_vec(),
+        };
+        // 1.234mA DC
+        let frame = [0x9b, 0xf0, 0x04, 0x00, 0xd2, 0x04];
+        let records = vec![
+            record(0, OW18E_NOTIFY_CHARACTERISTIC, &frame),
+            record(500, OW18E_WRITE_CHARACTERISTIC, &frame),
+            record(1000, OW18E_NOTIFY_CHARACTERISTIC, &[0x01]),
+            record(2500, OW18E_NOTIFY_CHARACTERISTIC, &frame),
+        ];
+
+        let (reading_tx, mut reading_rx) = watch::channel(None);
+        let start = time::Instant::now();
+        let task = start_replay_task(CancellationToken::new(), reading_tx, records);
+
+        let mut samples = Vec::new();
+        while reading_rx.changed().await.is_ok() {
+            samples.push(reading_rx.borrow_and_update().unwrap());
+        }
+        task.await.unwrap().unwrap();
+
+        let samples: Vec<_> = samples
+            .iter()
+            .map(|sample| {
+                (
+                    sample.sequence,
+                    sample.received_at.unwrap() - start,
+                    sample.timestamp,
+                    sample.value.amperes(),
+                )
+            })
+            .collect();
+        let timestamp = |millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+        let amperes = Some(Measurement::Value(1.234e-3));
+        assert_eq!(
+            samples,
+            [
+                (0, Duration::ZERO, timestamp(1_700_000_000_000), amperes),
+                (
+                    1,
+                    Duration::from_millis(2500),
+                    timestamp(1_700_000_002_500),
+                    amperes
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn adapter_names() {
+        let info = "hci10 (usb:v1D6Bp0246d0540)";
+        assert!(adapter_matches(info, "hci10"));
+        assert!(!adapter_matches(info, "hci1"));
+        assert!(!adapter_matches(info, "usb"));
+        assert!(adapter_matches(info, "usb:v1D6Bp0246d0540"));
     }
 }
@@ -0,0 +1,201 @@
+use btleplug::api::ValueNotification;
+use std::{
+    fmt::{self, Display},
+    fs::{self, File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::Path,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+/// A raw notification received from the multimeter, as stored in a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Time since the Unix epoch at which the notification was received
+    pub timestamp: Duration,
+    pub uuid: Uuid,
+    pub bytes: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn new(notification: &ValueNotification) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            uuid: notification.uuid,
+            bytes: notification.value.clone(),
+        }
+    }
+}
+
+/// Formats the record as one line of a capture file:
+/// `<seconds since the Unix epoch> <characteristic UUID> <bytes as hex, or - if empty>`
+impl Display for CaptureRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.6} {} ", self.timestamp.as_secs_f64(), self.uuid)?;
+        if self.bytes.is_empty() {
+            return write!(f, "-");
+        }
+        for byte in &self.bytes {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CaptureRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_whitespace();
+        let (Some(timestamp), Some(uuid), bytes, None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!("expected `<timestamp> <uuid> <bytes>`, got `{s}`"));
+        };
+
+        let timestamp =
+            parse_timestamp(timestamp).ok_or_else(|| format!("invalid timestamp `{timestamp}`"))?;
+        let uuid = uuid
+            .parse()
+            .map_err(|e| format!("invalid UUID `{uuid}`: {e}"))?;
+        // Older captures wrote empty notifications without a bytes field.
+        let bytes = bytes.filter(|bytes| *bytes != "-").unwrap_or_default();
+        let bytes = (0..bytes.len())
+            .step_by(2)
+            .map(|i| {
+                bytes
+                    .get(i..i + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                    .ok_or_else(|| format!("invalid hex bytes `{bytes}`"))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            timestamp,
+            uuid,
+            bytes,
+        })
+    }
+}
+
+/// Parses `<seconds>.<fraction>` exactly, which parsing as `f64` wouldn't at microsecond
+/// resolution.
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let (secs, fraction) = timestamp.split_once('.').unwrap_or((timestamp, ""));
+    if !secs.bytes().all(|b| b.is_ascii_digit())
+        || !fraction.bytes().all(|b| b.is_ascii_digit())
+        || fraction.len() > 9
+    {
+        return None;
+    }
+
+    let nanos = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u32>().ok()? * 10_u32.pow(9 - fraction.len() as u32)
+    };
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+/// Reads all records of a capture file written by [`CaptureWriter`]. Invalid records are skipped
+/// with a warning, so that one damaged line doesn't make the whole capture unusable.
+pub fn read(path: &Path) -> io::Result<Vec<CaptureRecord>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter_map(|(i, line)| match line.parse() {
+            Ok(record) => Some(record),
+            Err(message) => {
+                eprintln!(
+                    "Skipping invalid record in line {} of the capture: {message}",
+                    i + 1
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/// Writes every raw notification to a capture file, including the ones that can't be parsed.
+pub struct CaptureWriter {
+    out: LineWriter<File>,
+}
+
+impl CaptureWriter {
+    /// Creates a new capture file. Fails if the file exists, so that an earlier capture isn't
+    /// overwritten.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: LineWriter::new(OpenOptions::new().write(true).create_new(true).open(path)?),
+        })
+    }
+
+    pub fn write(&mut self, notification: &ValueNotification) -> io::Result<()> {
+        writeln!(self.out, "{}", CaptureRecord::new(notification))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let uuid = "0000fff4-0000-1000-8000-00805f9b34fb".parse().unwrap();
+        for bytes in [vec![0x9b, 0xf0, 0x04, 0x00, 0xd2, 0x04], vec![0x01], vec![]] {
+            let record = CaptureRecord {
+                timestamp: Duration::from_micros(1_700_000_000_123_456),
+                uuid,
+                bytes,
+            };
+            let line = record.to_string();
+            assert_eq!(line.parse::<CaptureRecord>(), Ok(record), "{line}");
+        }
+    }
+
+    #[test]
+    fn keeps_existing_capture() {
+        let path =
+            std::env::temp_dir().join(format!("cap-reformer-{}.capture", std::process::id()));
+        fs::write(&path, "earlier capture\n").unwrap();
+
+        let err = CaptureWriter::create(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "earlier capture\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_records() {
+        let record: CaptureRecord = "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb 9bf0"
+            .parse()
+            .unwrap();
+        assert_eq!(record.timestamp, Duration::from_millis(1_700_000_000_500));
+        assert_eq!(record.bytes, [0x9b, 0xf0]);
+
+        let empty: CaptureRecord = "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb -"
+            .parse()
+            .unwrap();
+        assert!(empty.bytes.is_empty());
+        let empty: CaptureRecord = "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb "
+            .parse()
+            .unwrap();
+        assert!(empty.bytes.is_empty());
+
+        for invalid in [
+            "",
+            "1700000000.5",
+            "x 0000fff4-0000-1000-8000-00805f9b34fb 9bf0",
+            "1700000000.5 uuid 9bf0",
+            "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb 9bf",
+            "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb 9bxx",
+            "1700000000.5 0000fff4-0000-1000-8000-00805f9b34fb 9bf0 extra",
+        ] {
+            assert!(invalid.parse::<CaptureRecord>().is_err(), "{invalid}");
+        }
+    }
+}
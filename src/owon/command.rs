@@ -0,0 +1,63 @@
+use std::str::FromStr;
+
+/// A command for the OW18E, written to its write characteristic.
+///
+/// The commands mirror the buttons of the multimeter. Each one is encoded as the button number,
+/// followed by the number of presses, where 0 presses means a long press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Cycles through the functions of the current rotary switch position
+    Select,
+    /// Switches to manual ranging, or steps to the next higher range, wrapping around to the
+    /// lowest one
+    Range,
+    /// Steps to the next lower range. The OW18E has no button for this, so
+    /// [`Meter`](super::Meter) emulates it with [`Command::Range`] presses, see
+    /// [`Meter::range_down`](super::Meter::range_down).
+    RangeDown,
+    /// Switches back to autoranging
+    AutoRange,
+    /// Toggles hold mode
+    Hold,
+    /// Toggles the backlight
+    Backlight,
+    /// Toggles relative mode, using the current value as the reference
+    Relative,
+}
+
+impl Command {
+    /// The bytes to write, or `None` if the command has no button.
+    pub fn bytes(&self) -> Option<[u8; 2]> {
+        Some(match self {
+            Command::Select => [0x01, 0x01],
+            Command::Range => [0x02, 0x01],
+            Command::RangeDown => return None,
+            Command::AutoRange => [0x02, 0x00],
+            Command::Hold => [0x03, 0x01],
+            Command::Backlight => [0x03, 0x00],
+            Command::Relative => [0x04, 0x01],
+        })
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "select" => Command::Select,
+            "range" => Command::Range,
+            "range-down" => Command::RangeDown,
+            "auto-range" => Command::AutoRange,
+            "hold" => Command::Hold,
+            "backlight" => Command::Backlight,
+            "relative" => Command::Relative,
+            _ => {
+                return Err(format!(
+                    "unknown command `{s}`, expected one of select, range, range-down, \
+                     auto-range, hold, backlight, relative"
+                ))
+            }
+        })
+    }
+}
@@ -1,38 +1,140 @@
-use bytemuck::CheckedBitPattern;
-
-#[repr(u16)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, CheckedBitPattern)]
-#[allow(dead_code)]
+/// Measurement mode of the multimeter, i.e. the function and range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mode {
-    DcMillivolt = 0xF018,
-    DcVolt = 0xF020,
-    AcMillivolt = 0xF058,
-    AcVolt = 0xF060,
-    DcMicroAmpere = 0xF090,
-    DcMilliAmpere = 0xF098,
-    DcAmpere = 0xF0A0,
-    AcMicroAmpere = 0xF0D0,
-    AcMilliAmpere = 0xF0D8,
-    AcAmpere = 0xF0E0,
-    Ohm = 0xF120,
-    KiloOhm = 0xF128,
-    MegaOhm = 0xF130,
-    NanoFarad = 0xF148,
-    MicroFarad = 0xF150,
-    MilliFarad = 0xF158,
-    Farad = 0xF160,
-    Hertz = 0xF1A0,
-    KiloHertz = 0xF1A8,
-    MegaHertz = 0xF1B0,
-    DutyCyclePercent = 0xF1E0,
-    DegreesCelsius = 0xF220,
-    DegreesFahrenheit = 0xF260,
-    DiodeVolt = 0xF2A0,
-    ContinuityOhm = 0xF2E0,
-    NearField = 0xF360,
+    DcMillivolt,
+    DcVolt,
+    AcMillivolt,
+    AcVolt,
+    DcMicroAmpere,
+    DcMilliAmpere,
+    DcAmpere,
+    AcMicroAmpere,
+    AcMilliAmpere,
+    AcAmpere,
+    Ohm,
+    KiloOhm,
+    MegaOhm,
+    NanoFarad,
+    MicroFarad,
+    MilliFarad,
+    Farad,
+    Hertz,
+    KiloHertz,
+    MegaHertz,
+    DutyCyclePercent,
+    DegreesCelsius,
+    DegreesFahrenheit,
+    DiodeVolt,
+    ContinuityOhm,
+    NearField,
+    /// A mode word not known to this program
+    Unknown(u16),
+}
+
+impl From<u16> for Mode {
+    fn from(raw: u16) -> Self {
+        match raw {
+            0xF018 => Mode::DcMillivolt,
+            0xF020 => Mode::DcVolt,
+            0xF058 => Mode::AcMillivolt,
+            0xF060 => Mode::AcVolt,
+            0xF090 => Mode::DcMicroAmpere,
+            0xF098 => Mode::DcMilliAmpere,
+            0xF0A0 => Mode::DcAmpere,
+            0xF0D0 => Mode::AcMicroAmpere,
+            0xF0D8 => Mode::AcMilliAmpere,
+            0xF0E0 => Mode::AcAmpere,
+            0xF120 => Mode::Ohm,
+            0xF128 => Mode::KiloOhm,
+            0xF130 => Mode::MegaOhm,
+            0xF148 => Mode::NanoFarad,
+            0xF150 => Mode::MicroFarad,
+            0xF158 => Mode::MilliFarad,
+            0xF160 => Mode::Farad,
+            0xF1A0 => Mode::Hertz,
+            0xF1A8 => Mode::KiloHertz,
+            0xF1B0 => Mode::MegaHertz,
+            0xF1E0 => Mode::DutyCyclePercent,
+            0xF220 => Mode::DegreesCelsius,
+            0xF260 => Mode::DegreesFahrenheit,
+            0xF2A0 => Mode::DiodeVolt,
+            0xF2E0 => Mode::ContinuityOhm,
+            0xF360 => Mode::NearField,
+            raw => Mode::Unknown(raw),
+        }
+    }
+}
+
+/// Base SI unit (or similar) of a [`Mode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Unit {
+    Volt,
+    Ampere,
+    Ohm,
+    Farad,
+    Hertz,
+    Percent,
+    DegreesCelsius,
+    DegreesFahrenheit,
+    /// No meaningful unit, for near field (non-contact voltage) detection or unknown modes
+    None,
 }
 
 impl Mode {
+    /// Unit of the readings in this mode, after applying [`Mode::scale`].
+    pub fn unit(&self) -> Unit {
+        match self {
+            Mode::DcMillivolt
+            | Mode::DcVolt
+            | Mode::AcMillivolt
+            | Mode::AcVolt
+            | Mode::DiodeVolt => Unit::Volt,
+            Mode::DcMicroAmpere
+            | Mode::DcMilliAmpere
+            | Mode::DcAmpere
+            | Mode::AcMicroAmpere
+            | Mode::AcMilliAmpere
+            | Mode::AcAmpere => Unit::Ampere,
+            Mode::Ohm | Mode::KiloOhm | Mode::MegaOhm | Mode::ContinuityOhm => Unit::Ohm,
+            Mode::NanoFarad | Mode::MicroFarad | Mode::MilliFarad | Mode::Farad => Unit::Farad,
+            Mode::Hertz | Mode::KiloHertz | Mode::MegaHertz => Unit::Hertz,
+            Mode::DutyCyclePercent => Unit::Percent,
+            Mode::DegreesCelsius => Unit::DegreesCelsius,
+            Mode::DegreesFahrenheit => Unit::DegreesFahrenheit,
+            Mode::NearField | Mode::Unknown(_) => Unit::None,
+        }
+    }
+
+    /// Factor to convert a reading in this mode to [`Mode::unit`].
+    pub fn scale(&self) -> f64 {
+        match self {
+            Mode::NanoFarad => 1e-9,
+            Mode::DcMicroAmpere | Mode::AcMicroAmpere | Mode::MicroFarad => 1e-6,
+            Mode::DcMillivolt
+            | Mode::AcMillivolt
+            | Mode::DcMilliAmpere
+            | Mode::AcMilliAmpere
+            | Mode::MilliFarad => 1e-3,
+            Mode::KiloOhm | Mode::KiloHertz => 1e3,
+            Mode::MegaOhm | Mode::MegaHertz => 1e6,
+            _ => 1.0,
+        }
+    }
+
+    /// Whether this mode measures alternating voltage or current.
+    pub fn is_ac(&self) -> bool {
+        matches!(
+            self,
+            Mode::AcMillivolt
+                | Mode::AcVolt
+                | Mode::AcMicroAmpere
+                | Mode::AcMilliAmpere
+                | Mode::AcAmpere
+        )
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Mode::DcMillivolt => "mV DC",
@@ -61,6 +163,7 @@ impl Mode {
             Mode::DiodeVolt => "V ―⯈⊢",
             Mode::ContinuityOhm => "Ohm ))))",
             Mode::NearField => "(NF)",
+            Mode::Unknown(_) => "(unknown mode)",
         }
     }
 }
@@ -1,21 +1,30 @@
-use super::mode::Mode;
+use super::mode::{Mode, Unit};
+use crate::meter::Measurement;
 use binrw::BinRead;
-use std::{fmt::Debug, io::Cursor};
+use std::{
+    fmt::{Debug, Display},
+    io::Cursor,
+};
 
 #[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SerdeReading", from = "SerdeReading")
+)]
 pub struct Reading {
     pub mode: Mode,
     divider: u8,
     raw_value: u16,
-    hold: bool,
-    relative: bool,
-    autoranging: bool,
-    low_battery: bool,
+    pub hold: bool,
+    pub relative: bool,
+    pub autoranging: bool,
+    pub low_battery: bool,
 }
 
 impl Reading {
-    pub fn new(raw: RawMessage) -> Option<Self> {
-        let mode = bytemuck::checked::try_pod_read_unaligned(&raw.mode().to_ne_bytes()).ok()?;
+    pub fn new(raw: RawMessage) -> Self {
+        let mode = Mode::from(raw.mode());
         let divider = raw.divider();
         let raw_value = raw.raw_value;
         let hold = raw.flags & 0x1 != 0;
@@ -23,7 +32,7 @@ impl Reading {
         let autoranging = raw.flags & 0x4 != 0;
         let low_battery = raw.flags & 0x8 != 0;
 
-        Some(Self {
+        Self {
             mode,
             divider,
             raw_value,
@@ -31,13 +40,23 @@ impl Reading {
             relative,
             autoranging,
             low_battery,
-        })
+        }
     }
 
-    pub fn value(&self) -> f64 {
+    /// Identifies the range of the reading by its mode and decimal point position.
+    pub fn range(&self) -> (Mode, u8) {
+        (self.mode, self.divider)
+    }
+
+    /// The value as shown on the display, in the unit of the mode.
+    pub fn value(&self) -> Measurement {
+        if self.mode == Mode::NearField {
+            return Measurement::Blank;
+        }
+
         let num = self.raw_value & 0x7FFF;
         if num == 0x7FFF {
-            return f64::NAN;
+            return Measurement::Overload;
         }
 
         let mut num = num as f64;
@@ -46,7 +65,17 @@ impl Reading {
         }
 
         let divider = 10.0_f64.powi(self.divider as i32);
-        num / divider
+        Measurement::Value(num / divider)
+    }
+
+    /// The value in the base unit of the mode, e.g. A instead of mA.
+    pub fn si_value(&self) -> Measurement {
+        self.value().map(|value| value * self.mode.scale())
+    }
+
+    /// The value in A, if the multimeter is in one of the DC current modes.
+    pub fn amperes(&self) -> Option<Measurement> {
+        (self.mode.unit() == Unit::Ampere && !self.mode.is_ac()).then(|| self.si_value())
     }
 }
 
@@ -55,20 +84,7 @@ impl Debug for Reading {
         let mut f = f.debug_struct("Message");
         f.field("raw", &self.raw_value);
 
-        let value = self.value();
-        if value.is_nan() {
-            f.field("value", &format_args!("-- {}", self.mode.as_str()));
-        } else {
-            f.field(
-                "value",
-                &format_args!(
-                    "{:.*} {}",
-                    usize::from(self.divider),
-                    value,
-                    self.mode.as_str(),
-                ),
-            );
-        };
+        f.field("value", &format_args!("{self}"));
 
         f.field("hold", &self.hold)
             .field("relative", &self.relative)
@@ -78,6 +94,76 @@ impl Debug for Reading {
     }
 }
 
+/// Serialized form of a [`Reading`]: the raw fields, plus the decoded values for convenience.
+/// The decoded values are ignored when deserializing.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeReading {
+    mode: Mode,
+    divider: u8,
+    raw_value: u16,
+    hold: bool,
+    relative: bool,
+    autoranging: bool,
+    low_battery: bool,
+    #[serde(skip_deserializing)]
+    value: Option<Measurement>,
+    #[serde(skip_deserializing)]
+    unit: Option<Unit>,
+    #[serde(skip_deserializing)]
+    si_value: Option<Measurement>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Reading> for SerdeReading {
+    fn from(reading: Reading) -> Self {
+        Self {
+            mode: reading.mode,
+            divider: reading.divider,
+            raw_value: reading.raw_value,
+            hold: reading.hold,
+            relative: reading.relative,
+            autoranging: reading.autoranging,
+            low_battery: reading.low_battery,
+            value: Some(reading.value()),
+            unit: Some(reading.mode.unit()),
+            si_value: Some(reading.si_value()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SerdeReading> for Reading {
+    fn from(reading: SerdeReading) -> Self {
+        Self {
+            mode: reading.mode,
+            divider: reading.divider,
+            raw_value: reading.raw_value,
+            hold: reading.hold,
+            relative: reading.relative,
+            autoranging: reading.autoranging,
+            low_battery: reading.low_battery,
+        }
+    }
+}
+
+/// Formats the reading like the multimeter's display, e.g. `-1.234 mA DC` or `OL kΩ`.
+impl Display for Reading {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.value() {
+            Measurement::Value(value) => write!(
+                f,
+                "{:.*} {}",
+                usize::from(self.divider),
+                value,
+                self.mode.as_str()
+            ),
+            Measurement::Overload => write!(f, "OL {}", self.mode.as_str()),
+            Measurement::Blank => write!(f, "-- {}", self.mode.as_str()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, BinRead)]
 #[br(little)]
 pub struct RawMessage {
@@ -99,5 +185,285 @@ impl RawMessage {
 
 pub fn parse(message: &[u8]) -> Option<Reading> {
     let raw = RawMessage::read(&mut Cursor::new(message)).ok()?;
-    Reading::new(raw)
+    Some(Reading::new(raw))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Builds a frame as sent by the OW18E.
+    fn frame(mode: u16, divider: u8, flags: u8, raw_value: u16) -> [u8; 6] {
+        let [mode_lo, mode_hi] = (mode | u16::from(divider)).to_le_bytes();
+        let [value_lo, value_hi] = raw_value.to_le_bytes();
+        [mode_lo, mode_hi, flags, 0x00, value_lo, value_hi]
+    }
+
+    const MODES: [(u16, Mode); 26] = [
+        (0xF018, Mode::DcMillivolt),
+        (0xF020, Mode::DcVolt),
+        (0xF058, Mode::AcMillivolt),
+        (0xF060, Mode::AcVolt),
+        (0xF090, Mode::DcMicroAmpere),
+        (0xF098, Mode::DcMilliAmpere),
+        (0xF0A0, Mode::DcAmpere),
+        (0xF0D0, Mode::AcMicroAmpere),
+        (0xF0D8, Mode::AcMilliAmpere),
+        (0xF0E0, Mode::AcAmpere),
+        (0xF120, Mode::Ohm),
+        (0xF128, Mode::KiloOhm),
+        (0xF130, Mode::MegaOhm),
+        (0xF148, Mode::NanoFarad),
+        (0xF150, Mode::MicroFarad),
+        (0xF158, Mode::MilliFarad),
+        (0xF160, Mode::Farad),
+        (0xF1A0, Mode::Hertz),
+        (0xF1A8, Mode::KiloHertz),
+        (0xF1B0, Mode::MegaHertz),
+        (0xF1E0, Mode::DutyCyclePercent),
+        (0xF220, Mode::DegreesCelsius),
+        (0xF260, Mode::DegreesFahrenheit),
+        (0xF2A0, Mode::DiodeVolt),
+        (0xF2E0, Mode::ContinuityOhm),
+        (0xF360, Mode::NearField),
+    ];
+
+    /// Checks the frames of a corpus in the format of `testdata/synthetic_frames.txt` against
+    /// their expected flags, value and display, and returns their modes.
+    fn check_frames(corpus: &str) -> Vec<Mode> {
+        let mut modes = Vec::new();
+        for line in corpus
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let mut fields = line.splitn(4, ' ');
+            let (Some(bytes), Some(flags), Some(value), Some(display)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                panic!("invalid frame line `{line}`");
+            };
+            let value = match value {
+                "OL" => Measurement::Overload,
+                "--" => Measurement::Blank,
+                value => Measurement::Value(value.parse().unwrap()),
+            };
+            let bytes: Vec<u8> = (0..bytes.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&bytes[i..i + 2], 16).unwrap())
+                .collect();
+
+            let reading = parse(&bytes).unwrap();
+            let actual_flags: String = [
+                (reading.hold, 'H'),
+                (reading.relative, 'R'),
+                (reading.autoranging, 'A'),
+                (reading.low_battery, 'L'),
+            ]
+            .iter()
+            .map(|&(set, flag)| if set { flag } else { '-' })
+            .collect();
+            assert_eq!(actual_flags, flags, "{line}");
+            assert_eq!(reading.value(), value, "{line}");
+            assert_eq!(reading.to_string(), display, "{line}");
+            modes.push(reading.mode);
+        }
+        modes
+    }
+
+    /// Built from the protocol, the synthetic frames only guard against regressions.
+    #[test]
+    fn synthetic_frames() {
+        let modes = check_frames(include_str!("testdata/synthetic_frames.txt"));
+        for (_, mode) in MODES {
+            assert!(modes.contains(&mode), "{mode:?} missing from the frames");
+        }
+    }
+
+    /// Frames recorded from a real multimeter, which check the decoder's understanding of the
+    /// protocol.
+    #[test]
+    #[ignore = "no frames have been recorded from a real OW18E yet"]
+    fn recorded_frames() {
+        let modes = check_frames(include_str!("testdata/recorded_frames.txt"));
+        assert!(!modes.is_empty(), "no recorded frames");
+    }
+
+    #[test]
+    fn every_mode() {
+        for (word, mode) in MODES {
+            let reading = parse(&frame(word, 1, 0, 123)).unwrap();
+            assert_eq!(reading.mode, mode);
+
+            let expected = if mode == Mode::NearField {
+                Measurement::Blank
+            } else {
+                Measurement::Value(12.3)
+            };
+            assert_eq!(reading.value(), expected, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn dividers() {
+        for (divider, expected) in [(0, 1234.0), (1, 123.4), (2, 12.34), (3, 1.234), (4, 0.1234)] {
+            let reading = parse(&frame(0xF098, divider, 0, 1234)).unwrap();
+            assert_eq!(reading.value(), Measurement::Value(expected));
+        }
+    }
+
+    #[test]
+    fn sign() {
+        let positive = parse(&frame(0xF098, 3, 0, 1234)).unwrap();
+        let negative = parse(&frame(0xF098, 3, 0, 0x8000 | 1234)).unwrap();
+        assert_eq!(positive.value(), Measurement::Value(1.234));
+        assert_eq!(negative.value(), Measurement::Value(-1.234));
+    }
+
+    #[test]
+    fn overload() {
+        for raw_value in [0x7FFF, 0xFFFF] {
+            let reading = parse(&frame(0xF098, 3, 0, raw_value)).unwrap();
+            assert_eq!(reading.value(), Measurement::Overload);
+            assert_eq!(reading.si_value(), Measurement::Overload);
+            assert_eq!(reading.amperes(), Some(Measurement::Overload));
+        }
+    }
+
+    #[test]
+    fn flags() {
+        let flag_of = |reading: Reading| {
+            [
+                reading.hold,
+                reading.relative,
+                reading.autoranging,
+                reading.low_battery,
+            ]
+        };
+
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x00, 0)).unwrap()),
+            [false; 4]
+        );
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x01, 0)).unwrap()),
+            [true, false, false, false]
+        );
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x02, 0)).unwrap()),
+            [false, true, false, false]
+        );
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x04, 0)).unwrap()),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x08, 0)).unwrap()),
+            [false, false, false, true]
+        );
+        assert_eq!(
+            flag_of(parse(&frame(0xF098, 3, 0x0F, 0)).unwrap()),
+            [true; 4]
+        );
+    }
+
+    #[test]
+    fn si_value_and_amperes() {
+        let micro = parse(&frame(0xF090, 1, 0, 567)).unwrap();
+        let milli = parse(&frame(0xF098, 3, 0, 1234)).unwrap();
+        let ac = parse(&frame(0xF0D8, 3, 0, 1234)).unwrap();
+        let volt = parse(&frame(0xF020, 2, 0, 1234)).unwrap();
+
+        let Some(Measurement::Value(amperes)) = micro.amperes() else {
+            panic!("no DC current: {micro:?}");
+        };
+        assert!((amperes - 56.7e-6).abs() < 1e-12);
+        let Some(Measurement::Value(amperes)) = milli.amperes() else {
+            panic!("no DC current: {milli:?}");
+        };
+        assert!((amperes - 1.234e-3).abs() < 1e-12);
+        assert_eq!(ac.amperes(), None);
+        assert_eq!(volt.amperes(), None);
+    }
+
+    #[test]
+    fn all_mode_words() {
+        for word in 0..=u16::MAX {
+            let mode = Mode::from(word);
+            match MODES.iter().find(|(known, _)| *known == word) {
+                Some((_, known)) => assert_eq!(mode, *known),
+                None => assert_eq!(mode, Mode::Unknown(word)),
+            }
+        }
+    }
+
+    #[test]
+    fn short_frames() {
+        let frame = frame(0xF098, 3, 0, 1234);
+        for len in 0..frame.len() {
+            assert_eq!(parse(&frame[..len]), None);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_schema() {
+        let reading = parse(&frame(0xF098, 3, 0x05, 0x8000 | 1234)).unwrap();
+        let json = serde_json::to_value(reading).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "mode": "DcMilliAmpere",
+                "divider": 3,
+                "raw_value": 0x8000 | 1234,
+                "hold": true,
+                "relative": false,
+                "autoranging": true,
+                "low_battery": false,
+                "value": { "Value": -1.234 },
+                "unit": "Ampere",
+                "si_value": { "Value": -0.001234 },
+            })
+        );
+
+        let unknown = parse(&frame(0x1230, 0, 0, 0x7FFF)).unwrap();
+        let json = serde_json::to_value(unknown).unwrap();
+        assert_eq!(json["mode"], serde_json::json!({ "Unknown": 0x1230 }));
+        assert_eq!(json["value"], serde_json::json!("Overload"));
+
+        assert_eq!(serde_json::from_value::<Reading>(json).unwrap(), unknown);
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..16)) {
+            let reading = parse(&bytes);
+            prop_assert_eq!(reading.is_some(), bytes.len() >= 6);
+        }
+
+        #[test]
+        fn raw_message_fields(
+            mode_and_divider: u16,
+            flags: u8,
+            unknown: u8,
+            raw_value: u16,
+        ) {
+            let [mode_lo, mode_hi] = mode_and_divider.to_le_bytes();
+            let [value_lo, value_hi] = raw_value.to_le_bytes();
+            let bytes = [mode_lo, mode_hi, flags, unknown, value_lo, value_hi];
+
+            let raw = RawMessage::read(&mut Cursor::new(&bytes)).unwrap();
+            prop_assert_eq!(raw.mode() | u16::from(raw.divider()), mode_and_divider);
+            prop_assert_eq!(raw.flags, flags);
+            prop_assert_eq!(raw._unknown, unknown);
+            prop_assert_eq!(raw.raw_value, raw_value);
+
+            let reading = Reading::new(raw);
+            prop_assert_eq!(reading.mode, Mode::from(mode_and_divider & !0x7));
+            prop_assert_eq!(reading.hold, flags & 0x1 != 0);
+            prop_assert_eq!(reading.relative, flags & 0x2 != 0);
+            prop_assert_eq!(reading.autoranging, flags & 0x4 != 0);
+            prop_assert_eq!(reading.low_battery, flags & 0x8 != 0);
+        }
+    }
 }
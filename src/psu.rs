@@ -0,0 +1,127 @@
+use crate::sample::Sample;
+use snafu::Snafu;
+use std::{error::Error, future::Future};
+
+#[derive(Debug, Snafu)]
+pub enum PowerSupplyError {
+    /// Error of the power supply backend, e.g. a communication error
+    Backend {
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+/// Protection state of a power supply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Protection {
+    None,
+    /// Over-voltage protection tripped
+    OverVoltage,
+    /// Over-current protection tripped
+    OverCurrent,
+    /// Protection state not known to this program
+    Unknown(u16),
+}
+
+/// Regulation mode of a power supply output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegulationMode {
+    ConstantVoltage,
+    ConstantCurrent,
+}
+
+/// Status of a power supply, see [`PowerSupply::status`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PsuStatus {
+    pub protection: Protection,
+    pub regulation_mode: RegulationMode,
+    pub output_enabled: bool,
+    /// Input voltage in V
+    pub input_voltage: f64,
+    /// Internal temperature in °C
+    pub temperature: f64,
+}
+
+/// Actual output of a power supply, see [`PowerSupply::voltage_and_current`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PsuOutput {
+    /// Output voltage in V
+    pub voltage: f64,
+    /// Output current in A
+    pub current: f64,
+}
+
+/// Setting ranges and resolution of a power supply, see [`PowerSupply::limits`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PsuLimits {
+    /// Maximum over-voltage protection setting in V
+    pub max_voltage_protection: f64,
+    /// Smallest step of the voltage setpoint and over-voltage protection in V
+    pub voltage_resolution: f64,
+    /// Smallest step of the current setpoint and over-current protection in A
+    pub current_resolution: f64,
+}
+
+impl PsuLimits {
+    /// Rounds `voltage` to a value the power supply can be set to.
+    pub fn round_voltage(&self, voltage: f64) -> f64 {
+        (voltage / self.voltage_resolution).round() * self.voltage_resolution
+    }
+
+    /// Rounds `current` to a value the power supply can be set to.
+    pub fn round_current(&self, current: f64) -> f64 {
+        (current / self.current_resolution).round() * self.current_resolution
+    }
+}
+
+/// A programmable power supply that can be used to reform a capacitor.
+///
+/// Voltages are in volts, currents in amperes.
+pub trait PowerSupply {
+    fn limits(&self) -> PsuLimits;
+
+    fn set_output(
+        &mut self,
+        enable: bool,
+    ) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+
+    fn set_voltage(
+        &mut self,
+        voltage: f64,
+    ) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+
+    fn set_current(
+        &mut self,
+        current: f64,
+    ) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+
+    fn set_voltage_protection(
+        &mut self,
+        voltage: f64,
+    ) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+
+    fn set_current_protection(
+        &mut self,
+        current: f64,
+    ) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+
+    /// Reads back the actual output voltage and current.
+    fn voltage_and_current(
+        &mut self,
+    ) -> impl Future<Output = Result<Sample<PsuOutput>, PowerSupplyError>> + Send;
+
+    /// Reads the protection, regulation and output state.
+    fn status(
+        &mut self,
+    ) -> impl Future<Output = Result<Sample<PsuStatus>, PowerSupplyError>> + Send;
+
+    /// Reads back the over-voltage and over-current protection settings.
+    fn voltage_and_current_protection(
+        &mut self,
+    ) -> impl Future<Output = Result<(f64, f64), PowerSupplyError>> + Send;
+
+    fn disconnect(&mut self) -> impl Future<Output = Result<(), PowerSupplyError>> + Send;
+}
@@ -1,96 +1,878 @@
-use snafu::Snafu;
-use std::time::Duration;
+use crate::{
+    psu::{
+        PowerSupply, PowerSupplyError, Protection, PsuLimits, PsuOutput, PsuStatus, RegulationMode,
+    },
+    sample::{Sample, Sequencer},
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{fmt, future::Future, ops::RangeInclusive, str::FromStr, time::Duration};
+use tokio::{net::TcpStream, time};
 use tokio_modbus::{
     client::{Context, Reader, Writer},
+    slave::SlaveContext,
     Slave, SlaveId,
 };
 use tokio_serial::{DataBits, SerialPortBuilderExt, StopBits};
 
 const SERIAL_TIMEOUT: Duration = Duration::from_millis(500);
-const VOLT_DIVIDER: f64 = 100.0;
-const CURRENT_DIVIDER: f64 = 1000.0;
+/// Baud rates the Riden supplies can be configured to
+pub const BAUD_RATES: [u32; 5] = [9600, 19200, 38400, 57600, 115200];
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+const TCP_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const INPUT_VOLTAGE_DIVIDER: f64 = 100.0;
+/// Number of times a setpoint is written before giving up, if setpoint verification is enabled
+const SETPOINT_WRITE_ATTEMPTS: usize = 3;
 
 #[derive(Debug, Snafu)]
 pub enum PsuModbusError {
     #[snafu(context(false))]
     SerialOpen { source: tokio_serial::Error },
+    /// Could not list the serial ports
+    SerialEnumerate { source: tokio_serial::Error },
+    #[snafu(display("Could not connect to {addr}"))]
+    TcpConnect {
+        addr: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Timed out connecting to {addr}"))]
+    TcpConnectTimeout {
+        addr: String,
+        source: tokio::time::error::Elapsed,
+    },
+    #[snafu(display("No response from PSU within {RESPONSE_TIMEOUT:?}"))]
+    ResponseTimeout { source: time::error::Elapsed },
+    #[snafu(display("PSU communication failed after {retries} retries"))]
+    RetriesExhausted {
+        retries: u32,
+        #[snafu(source(from(PsuModbusError, Box::new)))]
+        source: Box<PsuModbusError>,
+    },
     #[snafu(context(false))]
     ModbusProtocol { source: tokio_modbus::Error },
     #[snafu(context(false))]
     ModbusException { source: tokio_modbus::Exception },
+    #[snafu(display(
+        "Register {register} reads back as {actual} instead of {expected} after \
+         {SETPOINT_WRITE_ATTEMPTS} attempts"
+    ))]
+    SetpointMismatch {
+        register: u16,
+        expected: u16,
+        actual: u16,
+    },
+    #[snafu(display("Unsupported Riden model (type ID {id})"))]
+    UnsupportedModel { id: u16 },
+    #[snafu(display("{voltage}V is outside of the {model} voltage range (0-{max}V)"))]
+    VoltageOutOfRange {
+        model: Model,
+        voltage: f64,
+        max: f64,
+    },
+    #[snafu(display("{current}A is outside of the {model} current range (0-{max}A)"))]
+    CurrentOutOfRange {
+        model: Model,
+        current: f64,
+        max: f64,
+    },
+}
+
+impl From<PsuModbusError> for PowerSupplyError {
+    fn from(source: PsuModbusError) -> Self {
+        PowerSupplyError::Backend {
+            source: Box::new(source),
+        }
+    }
+}
+
+impl PsuModbusError {
+    /// Whether the error is caused by the connection, so retrying on a new connection may help.
+    fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            PsuModbusError::SerialOpen { .. }
+                | PsuModbusError::TcpConnect { .. }
+                | PsuModbusError::TcpConnectTimeout { .. }
+                | PsuModbusError::ResponseTimeout { .. }
+                | PsuModbusError::ModbusProtocol { .. }
+        )
+    }
+}
+
+/// How often and how fast to retry failed PSU requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a single request, 0 disables retrying
+    pub max_retries: u32,
+    /// Wait time before the first retry. Doubles with every further retry.
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const NONE: RetryPolicy = RetryPolicy {
+        max_retries: 0,
+        initial_backoff: Duration::ZERO,
+    };
+
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(MAX_RETRY_BACKOFF)
+    }
+}
+
+/// Supported Riden power supply models, identified by holding register 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Rd6006,
+    Rd6006P,
+    Rk6006,
+    Rd6012,
+    Rd6018,
+    Rd6024,
+}
+
+impl Model {
+    /// Maps the model ID register to a model. Only IDs documented for a model are accepted, as
+    /// guessing the model would scale setpoints wrongly.
+    pub fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            60061..=60064 => Model::Rd6006,
+            60065 => Model::Rd6006P,
+            60066 => Model::Rk6006,
+            60121..=60124 => Model::Rd6012,
+            // 60125..=60129 is the RD6012P, which isn't supported
+            60181..=60189 => Model::Rd6018,
+            60241..=60249 => Model::Rd6024,
+            _ => return None,
+        })
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Model::Rd6006 => "RD6006",
+            Model::Rd6006P => "RD6006P",
+            Model::Rk6006 => "RK6006",
+            Model::Rd6012 => "RD6012",
+            Model::Rd6018 => "RD6018",
+            Model::Rd6024 => "RD6024",
+        }
+    }
+
+    /// Register value per V
+    fn voltage_divider(&self) -> f64 {
+        match self {
+            Model::Rd6006P => 1000.0,
+            _ => 100.0,
+        }
+    }
+
+    /// Register value per A
+    fn current_divider(&self) -> f64 {
+        match self {
+            Model::Rd6006 | Model::Rk6006 => 1000.0,
+            Model::Rd6006P => 10000.0,
+            Model::Rd6012 | Model::Rd6018 | Model::Rd6024 => 100.0,
+        }
+    }
+
+    /// Maximum output voltage in V
+    pub fn max_voltage(&self) -> f64 {
+        60.0
+    }
+
+    /// Maximum output current in A
+    pub fn max_current(&self) -> f64 {
+        match self {
+            Model::Rd6006 | Model::Rd6006P | Model::Rk6006 => 6.0,
+            Model::Rd6012 => 12.0,
+            Model::Rd6018 => 18.0,
+            Model::Rd6024 => 24.0,
+        }
+    }
+
+    /// Maximum over-voltage protection setting in V
+    pub fn max_voltage_protection(&self) -> f64 {
+        self.max_voltage() + 2.0
+    }
+
+    /// Maximum over-current protection setting in A
+    pub fn max_current_protection(&self) -> f64 {
+        self.max_current() + 0.2
+    }
+
+    pub fn limits(&self) -> PsuLimits {
+        PsuLimits {
+            max_voltage_protection: self.max_voltage_protection(),
+            voltage_resolution: 1.0 / self.voltage_divider(),
+            current_resolution: 1.0 / self.current_divider(),
+        }
+    }
+
+    fn voltage_to_register(&self, voltage: f64, max: f64) -> Result<u16, PsuModbusError> {
+        ensure!(
+            (0.0..=max).contains(&voltage),
+            VoltageOutOfRangeSnafu {
+                model: *self,
+                voltage,
+                max
+            }
+        );
+        Ok((voltage * self.voltage_divider()).round() as u16)
+    }
+
+    fn current_to_register(&self, current: f64, max: f64) -> Result<u16, PsuModbusError> {
+        ensure!(
+            (0.0..=max).contains(&current),
+            CurrentOutOfRangeSnafu {
+                model: *self,
+                current,
+                max
+            }
+        );
+        Ok((current * self.current_divider()).round() as u16)
+    }
+
+    fn voltage_from_register(&self, value: u16) -> f64 {
+        value as f64 / self.voltage_divider()
+    }
+
+    fn current_from_register(&self, value: u16) -> f64 {
+        value as f64 / self.current_divider()
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the PSU is connected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Local serial port, e.g. `/dev/ttyUSB0` or `COM3`
+    Serial(String),
+    /// Modbus TCP, e.g. through a Modbus gateway (`tcp://host:port`)
+    Tcp(String),
+    /// Modbus RTU frames tunneled through a raw TCP socket, e.g. a ser2net bridge or the Riden
+    /// WiFi module (`rtu+tcp://host:port`)
+    RtuOverTcp(String),
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = s.strip_prefix("tcp://") {
+            Ok(Transport::Tcp(addr.to_owned()))
+        } else if let Some(addr) = s.strip_prefix("rtu+tcp://") {
+            Ok(Transport::RtuOverTcp(addr.to_owned()))
+        } else if s.contains("://") {
+            Err(format!("unsupported PSU transport `{s}`"))
+        } else {
+            Ok(Transport::Serial(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Serial(path) => f.write_str(path),
+            Transport::Tcp(addr) => write!(f, "tcp://{addr}"),
+            Transport::RtuOverTcp(addr) => write!(f, "rtu+tcp://{addr}"),
+        }
+    }
+}
+
+/// Identification registers of a Riden power supply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Identification {
+    pub type_id: u16,
+    pub serial_number: u32,
+    pub firmware: f64,
+}
+
+impl Identification {
+    async fn read(ctx: &mut Context) -> Result<Self, PsuModbusError> {
+        let regs = response(ctx.read_holding_registers(0, 4)).await?;
+        Ok(Self {
+            type_id: regs[0],
+            serial_number: (regs[1] as u32) << 16 | (regs[2] as u32),
+            firmware: regs[3] as f64 / 100.0,
+        })
+    }
+
+    pub fn model(&self) -> Option<Model> {
+        Model::from_id(self.type_id)
+    }
+}
+
+impl fmt::Display for Identification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Type: {}, FW: {}, SN: {:08X}",
+            self.type_id / 10,
+            self.firmware,
+            self.serial_number
+        )
+    }
 }
 
 pub async fn open_psu_modbus(
-    serial_path: String,
+    transport: &Transport,
     slave_id: SlaveId,
+    baud_rate: u32,
 ) -> Result<Psu, PsuModbusError> {
-    let serial_stream = tokio_serial::new(serial_path, 115200)
-        .data_bits(DataBits::Eight)
-        .stop_bits(StopBits::One)
-        .timeout(SERIAL_TIMEOUT)
-        .open_native_async()?;
+    let mut ctx = connect(transport, slave_id, baud_rate).await?;
 
-    let mut psu = tokio_modbus::client::rtu::attach_slave(serial_stream, Slave(slave_id));
+    let identification = Identification::read(&mut ctx).await?;
+    println!("{identification}");
 
-    let regs = psu.read_holding_registers(0, 4).await??;
-    let sn: u32 = (regs[1] as u32) << 16 | (regs[2] as u32);
+    let model = identification.model().context(UnsupportedModelSnafu {
+        id: identification.type_id,
+    })?;
     println!(
-        "Type: {}, FW: {}, SN: {sn:08X}",
-        regs[0] / 10,
-        regs[3] as f64 / 100.0
+        "Detected {model} (max. {}V, {}A)",
+        model.max_voltage(),
+        model.max_current()
     );
 
-    Ok(Psu { ctx: psu })
+    Ok(Psu {
+        ctx: Some(ctx),
+        transport: transport.clone(),
+        slave_id,
+        baud_rate,
+        model,
+        verify_setpoints: false,
+        retry_policy: RetryPolicy::NONE,
+        sequencer: Sequencer::default(),
+    })
+}
+
+/// A power supply found by [`discover`].
+#[derive(Debug, Clone)]
+pub struct DiscoveredPsu {
+    pub port: String,
+    pub slave_id: SlaveId,
+    pub baud_rate: u32,
+    pub identification: Identification,
+}
+
+/// Probes all serial ports for Riden power supplies, with every given slave ID and baud rate.
+///
+/// Once a port answers at one baud rate, the remaining baud rates are skipped for that port.
+pub async fn discover(
+    slave_ids: RangeInclusive<SlaveId>,
+    baud_rates: &[u32],
+) -> Result<Vec<DiscoveredPsu>, PsuModbusError> {
+    let ports = tokio_serial::available_ports().context(SerialEnumerateSnafu)?;
+
+    let mut found = Vec::new();
+    for port in ports {
+        let transport = Transport::Serial(port.port_name.clone());
+
+        for &baud_rate in baud_rates {
+            println!("Probing {} at {baud_rate} baud...", port.port_name);
+            let mut ctx = match connect(&transport, *slave_ids.start(), baud_rate).await {
+                Ok(ctx) => ctx,
+                Err(e) => {
+                    eprintln!("Could not open {}: {e}", port.port_name);
+                    break;
+                }
+            };
+
+            let found_before = found.len();
+            for slave_id in slave_ids.clone() {
+                ctx.set_slave(Slave(slave_id));
+                if let Ok(identification) = Identification::read(&mut ctx).await {
+                    found.push(DiscoveredPsu {
+                        port: port.port_name.clone(),
+                        slave_id,
+                        baud_rate,
+                        identification,
+                    });
+                }
+            }
+
+            let _ = ctx.disconnect().await;
+            if found.len() > found_before {
+                break;
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+async fn connect(
+    transport: &Transport,
+    slave_id: SlaveId,
+    baud_rate: u32,
+) -> Result<Context, PsuModbusError> {
+    Ok(match transport {
+        Transport::Serial(serial_path) => {
+            let serial_stream = tokio_serial::new(serial_path, baud_rate)
+                .data_bits(DataBits::Eight)
+                .stop_bits(StopBits::One)
+                .timeout(SERIAL_TIMEOUT)
+                .open_native_async()?;
+
+            tokio_modbus::client::rtu::attach_slave(serial_stream, Slave(slave_id))
+        }
+        Transport::Tcp(addr) => {
+            let stream = connect_tcp(addr).await?;
+            tokio_modbus::client::tcp::attach_slave(stream, Slave(slave_id))
+        }
+        Transport::RtuOverTcp(addr) => {
+            let stream = connect_tcp(addr).await?;
+            tokio_modbus::client::rtu::attach_slave(stream, Slave(slave_id))
+        }
+    })
+}
+
+/// Waits for the response to a Modbus request, failing if the PSU doesn't answer in time.
+async fn response<T>(
+    request: impl Future<Output = tokio_modbus::Result<T>>,
+) -> Result<T, PsuModbusError> {
+    Ok(time::timeout(RESPONSE_TIMEOUT, request)
+        .await
+        .context(ResponseTimeoutSnafu)???)
+}
+
+async fn connect_tcp(addr: &str) -> Result<TcpStream, PsuModbusError> {
+    let stream = time::timeout(TCP_CONNECT_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .context(TcpConnectTimeoutSnafu { addr })?
+        .context(TcpConnectSnafu { addr })?;
+    stream.set_nodelay(true).context(TcpConnectSnafu { addr })?;
+
+    Ok(stream)
 }
 
 pub struct Psu {
-    ctx: Context,
+    /// `None` while the connection needs to be reopened
+    ctx: Option<Context>,
+    transport: Transport,
+    slave_id: SlaveId,
+    baud_rate: u32,
+    model: Model,
+    verify_setpoints: bool,
+    retry_policy: RetryPolicy,
+    /// Numbers the samples read through [`PowerSupply`]
+    sequencer: Sequencer,
 }
 
 impl Psu {
+    /// Enables reading back setpoint registers after every write, retrying on mismatch.
+    pub fn set_verify_setpoints(&mut self, verify: bool) {
+        self.verify_setpoints = verify;
+    }
+
+    /// Sets how failed requests are retried.
+    ///
+    /// While retrying, no other request is sent, so the PSU holds the output at the last setpoints
+    /// that were successfully applied.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
     pub async fn disconnect(&mut self) -> Result<(), PsuModbusError> {
-        Ok(self.ctx.disconnect().await??)
+        match self.ctx.take() {
+            Some(mut ctx) => Ok(ctx.disconnect().await??),
+            None => Ok(()),
+        }
+    }
+
+    async fn context(&mut self) -> Result<&mut Context, PsuModbusError> {
+        let ctx = match self.ctx.take() {
+            Some(ctx) => ctx,
+            None => connect(&self.transport, self.slave_id, self.baud_rate).await?,
+        };
+        Ok(self.ctx.insert(ctx))
+    }
+
+    /// Handles a failed request. Returns `Ok` if the request should be retried.
+    async fn retry(
+        &mut self,
+        error: PsuModbusError,
+        retries: &mut u32,
+    ) -> Result<(), PsuModbusError> {
+        if !error.is_transient() {
+            return Err(error);
+        }
+        if *retries >= self.retry_policy.max_retries {
+            if *retries == 0 {
+                return Err(error);
+            }
+            return Err(error).context(RetriesExhaustedSnafu { retries: *retries });
+        }
+
+        *retries += 1;
+        let backoff = self.retry_policy.backoff(*retries);
+        eprintln!(
+            "PSU communication failed: {error}. Retry {retries}/{} in {backoff:?}...",
+            self.retry_policy.max_retries
+        );
+
+        // A late response to the failed request would be mistaken for the response to the
+        // next one, so always start over with a fresh connection.
+        self.ctx = None;
+        time::sleep(backoff).await;
+
+        Ok(())
+    }
+
+    async fn read_registers(&mut self, addr: u16, cnt: u16) -> Result<Vec<u16>, PsuModbusError> {
+        let mut retries = 0;
+        loop {
+            let res = match self.context().await {
+                Ok(ctx) => response(ctx.read_holding_registers(addr, cnt)).await,
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(regs) => return Ok(regs),
+                Err(e) => self.retry(e, &mut retries).await?,
+            }
+        }
+    }
+
+    async fn write_register(&mut self, addr: u16, value: u16) -> Result<(), PsuModbusError> {
+        let mut retries = 0;
+        loop {
+            let res = match self.context().await {
+                Ok(ctx) => response(ctx.write_single_register(addr, value)).await,
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(()) => return Ok(()),
+                Err(e) => self.retry(e, &mut retries).await?,
+            }
+        }
     }
 
     pub async fn set_output(&mut self, enable: bool) -> Result<(), PsuModbusError> {
-        Ok(self.ctx.write_single_register(18, enable as u16).await??)
+        self.write_register(18, enable as u16).await
     }
 
     pub async fn set_voltage(&mut self, voltage: f64) -> Result<(), PsuModbusError> {
-        Ok(self
-            .ctx
-            .write_single_register(8, (voltage * VOLT_DIVIDER) as u16)
-            .await??)
+        let value = self
+            .model
+            .voltage_to_register(voltage, self.model.max_voltage())?;
+        self.write_setpoint(8, value).await
     }
     pub async fn set_voltage_protection(&mut self, voltage: f64) -> Result<(), PsuModbusError> {
-        Ok(self
-            .ctx
-            .write_single_register(82, (voltage * VOLT_DIVIDER) as u16)
-            .await??)
+        let value = self
+            .model
+            .voltage_to_register(voltage, self.model.max_voltage_protection())?;
+        self.write_setpoint(82, value).await
     }
     pub async fn set_current(&mut self, current: f64) -> Result<(), PsuModbusError> {
-        Ok(self
-            .ctx
-            .write_single_register(9, (current * CURRENT_DIVIDER) as u16)
-            .await??)
+        let value = self
+            .model
+            .current_to_register(current, self.model.max_current())?;
+        self.write_setpoint(9, value).await
     }
     pub async fn set_current_protection(&mut self, current: f64) -> Result<(), PsuModbusError> {
-        Ok(self
-            .ctx
-            .write_single_register(83, (current * CURRENT_DIVIDER) as u16)
-            .await??)
+        let value = self
+            .model
+            .current_to_register(current, self.model.max_current_protection())?;
+        self.write_setpoint(83, value).await
+    }
+
+    async fn write_setpoint(&mut self, register: u16, value: u16) -> Result<(), PsuModbusError> {
+        if !self.verify_setpoints {
+            return self.write_register(register, value).await;
+        }
+
+        let mut actual = value;
+        for attempt in 1..=SETPOINT_WRITE_ATTEMPTS {
+            self.write_register(register, value).await?;
+            actual = self.read_registers(register, 1).await?[0];
+            if actual == value {
+                return Ok(());
+            }
+
+            eprintln!(
+                "Register {register} reads back as {actual} instead of {value} \
+                 (attempt {attempt}/{SETPOINT_WRITE_ATTEMPTS})"
+            );
+        }
+
+        SetpointMismatchSnafu {
+            register,
+            expected: value,
+            actual,
+        }
+        .fail()
+    }
+
+    pub async fn voltage_and_current(&mut self) -> Result<PsuOutput, PsuModbusError> {
+        let regs = self.read_registers(10, 2).await?;
+        Ok(PsuOutput {
+            voltage: self.model.voltage_from_register(regs[0]),
+            current: self.model.current_from_register(regs[1]),
+        })
     }
 
-    pub async fn voltage_and_current(&mut self) -> Result<(f64, f64), PsuModbusError> {
-        let regs = self.ctx.read_holding_registers(10, 2).await??;
+    pub async fn status(&mut self) -> Result<PsuStatus, PsuModbusError> {
+        // Registers 4 (temperature sign) to 18 (output enable)
+        let regs = self.read_registers(4, 15).await?;
+        let reg = |addr: usize| regs[addr - 4];
+
+        let protection = match reg(16) {
+            0 => Protection::None,
+            1 => Protection::OverVoltage,
+            2 => Protection::OverCurrent,
+            other => Protection::Unknown(other),
+        };
+        let regulation_mode = if reg(17) == 0 {
+            RegulationMode::ConstantVoltage
+        } else {
+            RegulationMode::ConstantCurrent
+        };
+        let temperature = if reg(4) == 0 {
+            reg(5) as f64
+        } else {
+            -(reg(5) as f64)
+        };
+
+        Ok(PsuStatus {
+            protection,
+            regulation_mode,
+            output_enabled: reg(18) != 0,
+            input_voltage: reg(14) as f64 / INPUT_VOLTAGE_DIVIDER,
+            temperature,
+        })
+    }
+
+    pub async fn voltage_and_current_protection(&mut self) -> Result<(f64, f64), PsuModbusError> {
+        let regs = self.read_registers(82, 2).await?;
         Ok((
-            regs[0] as f64 / VOLT_DIVIDER,
-            regs[1] as f64 / CURRENT_DIVIDER,
+            self.model.voltage_from_register(regs[0]),
+            self.model.current_from_register(regs[1]),
         ))
     }
+}
+
+impl PowerSupply for Psu {
+    fn limits(&self) -> PsuLimits {
+        self.model.limits()
+    }
+
+    async fn set_output(&mut self, enable: bool) -> Result<(), PowerSupplyError> {
+        Ok(Psu::set_output(self, enable).await?)
+    }
+
+    async fn set_voltage(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+        Ok(Psu::set_voltage(self, voltage).await?)
+    }
+
+    async fn set_current(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+        Ok(Psu::set_current(self, current).await?)
+    }
+
+    async fn set_voltage_protection(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+        Ok(Psu::set_voltage_protection(self, voltage).await?)
+    }
+
+    async fn set_current_protection(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+        Ok(Psu::set_current_protection(self, current).await?)
+    }
+
+    async fn voltage_and_current(&mut self) -> Result<Sample<PsuOutput>, PowerSupplyError> {
+        let output = Psu::voltage_and_current(self).await?;
+        Ok(self.sequencer.sample(output))
+    }
+
+    async fn status(&mut self) -> Result<Sample<PsuStatus>, PowerSupplyError> {
+        let status = Psu::status(self).await?;
+        Ok(self.sequencer.sample(status))
+    }
+
+    async fn voltage_and_current_protection(&mut self) -> Result<(f64, f64), PowerSupplyError> {
+        Ok(Psu::voltage_and_current_protection(self).await?)
+    }
+
+    async fn disconnect(&mut self) -> Result<(), PowerSupplyError> {
+        Ok(Psu::disconnect(self).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_ids() {
+        let cases = [
+            (60060, None),
+            (60061, Some(Model::Rd6006)),
+            (60062, Some(Model::Rd6006)),
+            (60064, Some(Model::Rd6006)),
+            (60065, Some(Model::Rd6006P)),
+            (60066, Some(Model::Rk6006)),
+            (60067, None),
+            (60069, None),
+            (60120, None),
+            (60121, Some(Model::Rd6012)),
+            (60124, Some(Model::Rd6012)),
+            (60125, None),
+            (60129, None),
+            (60180, None),
+            (60181, Some(Model::Rd6018)),
+            (60189, Some(Model::Rd6018)),
+            (60240, None),
+            (60241, Some(Model::Rd6024)),
+            (60249, Some(Model::Rd6024)),
+            (0, None),
+            (u16::MAX, None),
+        ];
+        for (id, model) in cases {
+            assert_eq!(Model::from_id(id), model, "{id}");
+        }
+    }
+
+    #[test]
+    fn scaling() {
+        let cases = [
+            (Model::Rd6006, 100.0, 1000.0),
+            (Model::Rd6006P, 1000.0, 10000.0),
+            (Model::Rk6006, 100.0, 1000.0),
+            (Model::Rd6012, 100.0, 100.0),
+            (Model::Rd6018, 100.0, 100.0),
+            (Model::Rd6024, 100.0, 100.0),
+        ];
+        for (model, voltage_divider, current_divider) in cases {
+            assert_eq!(model.voltage_divider(), voltage_divider, "{model:?}");
+            assert_eq!(model.current_divider(), current_divider, "{model:?}");
+        }
+
+        let register = Model::Rd6006.voltage_to_register(12.34, 60.0).unwrap();
+        assert_eq!(register, 1234);
+        assert_eq!(Model::Rd6006.voltage_from_register(register), 12.34);
+        let register = Model::Rd6006P.current_to_register(1.2345, 6.0).unwrap();
+        assert_eq!(register, 12345);
+        assert_eq!(Model::Rd6006P.current_from_register(register), 1.2345);
+        let register = Model::Rd6012.current_to_register(1.23, 12.0).unwrap();
+        assert_eq!(register, 123);
+
+        assert!(Model::Rd6006.voltage_to_register(60.1, 60.0).is_err());
+        assert!(Model::Rd6006.current_to_register(-0.1, 6.0).is_err());
+    }
+
+    #[test]
+    fn resolution() {
+        assert!((Model::Rk6006.limits().round_current(0.009) - 0.009).abs() < 1e-12);
+        assert!((Model::Rd6012.limits().round_current(0.009) - 0.01).abs() < 1e-12);
+        assert_eq!(Model::Rd6012.limits().round_current(0.004), 0.0);
+        assert_eq!(Model::Rd6006.limits().max_voltage_protection, 62.0);
+        assert!((Model::Rd6006.limits().round_voltage(10.504) - 10.5).abs() < 1e-12);
+        assert!((Model::Rd6006P.limits().round_voltage(10.504) - 10.504).abs() < 1e-12);
+    }
+
+    /// Error of an unreachable PSU, which is worth retrying.
+    fn connect_error() -> PsuModbusError {
+        PsuModbusError::TcpConnect {
+            addr: String::from("192.0.2.1:502"),
+            source: std::io::Error::from(std::io::ErrorKind::TimedOut),
+        }
+    }
+
+    fn psu(max_retries: u32) -> Psu {
+        Psu {
+            ctx: None,
+            transport: Transport::Tcp(String::from("192.0.2.1:502")),
+            slave_id: 1,
+            baud_rate: 115200,
+            model: Model::Rd6006,
+            verify_setpoints: false,
+            retry_policy: RetryPolicy {
+                max_retries,
+                initial_backoff: Duration::from_millis(250),
+            },
+            sequencer: Sequencer::default(),
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(250),
+        };
+        let backoffs: Vec<_> = (1..=6).map(|retry| policy.backoff(retry)).collect();
+        assert_eq!(
+            backoffs,
+            [250, 500, 1000, 2000, 4000, 5000].map(Duration::from_millis)
+        );
+        assert_eq!(policy.backoff(u32::MAX), MAX_RETRY_BACKOFF);
+        assert_eq!(RetryPolicy::NONE.backoff(1), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transient_errors() {
+        let timeout = time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert!(PsuModbusError::ResponseTimeout { source: timeout }.is_transient());
+        assert!(connect_error().is_transient());
+
+        let exception = PsuModbusError::from(tokio_modbus::Exception::IllegalDataAddress);
+        assert!(!exception.is_transient());
+        let mismatch = PsuModbusError::SetpointMismatch {
+            register: 8,
+            expected: 1000,
+            actual: 0,
+        };
+        assert!(!mismatch.is_transient());
+        let exhausted = PsuModbusError::RetriesExhausted {
+            retries: 1,
+            source: Box::new(connect_error()),
+        };
+        assert!(!exhausted.is_transient());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_with_backoff() {
+        let mut psu = psu(2);
+        let mut retries = 0;
+        let start = time::Instant::now();
+
+        psu.retry(connect_error(), &mut retries).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(250));
+        psu.retry(connect_error(), &mut retries).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(750));
+        assert_eq!(retries, 2);
+
+        let err = psu.retry(connect_error(), &mut retries).await.unwrap_err();
+        assert!(
+            matches!(
+                err,
+                PsuModbusError::RetriesExhausted { retries: 2, ref source }
+                    if matches!(**source, PsuModbusError::TcpConnect { .. })
+            ),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_retries() {
+        let mut retries = 0;
+        let err = psu(0)
+            .retry(connect_error(), &mut retries)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PsuModbusError::TcpConnect { .. }), "{err:?}");
 
-    pub async fn voltage(&mut self) -> Result<f64, PsuModbusError> {
-        let regs = self.ctx.read_holding_registers(10, 1).await??;
-        Ok(regs[0] as f64 / VOLT_DIVIDER)
+        let exception = PsuModbusError::from(tokio_modbus::Exception::IllegalDataAddress);
+        let err = psu(2).retry(exception, &mut retries).await.unwrap_err();
+        assert!(
+            matches!(err, PsuModbusError::ModbusException { .. }),
+            "{err:?}"
+        );
+        assert_eq!(retries, 0);
     }
 }
@@ -0,0 +1,49 @@
+use std::time::SystemTime;
+use tokio::time::Instant;
+
+/// A value received from a meter or power supply, with the time it was received.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sample<T> {
+    pub value: T,
+    /// Number of the sample, counting up from 0 per source
+    pub sequence: u64,
+    /// Time at which the sample was received, for measuring durations. Not serialized, as it is
+    /// only meaningful within this process, so `None` for a loaded sample.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub received_at: Option<Instant>,
+    /// Wall clock time at which the sample was received, for logs
+    pub timestamp: SystemTime,
+}
+
+impl<T> Sample<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Sample<U> {
+        Sample {
+            value: f(self.value),
+            sequence: self.sequence,
+            received_at: self.received_at,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Numbers the samples of one source.
+#[derive(Debug, Default)]
+pub struct Sequencer {
+    next: u64,
+}
+
+impl Sequencer {
+    /// Wraps `value` into a sample received now.
+    pub fn sample<T>(&mut self, value: T) -> Sample<T> {
+        let sequence = self.next;
+        self.next += 1;
+
+        Sample {
+            value,
+            sequence,
+            received_at: Some(Instant::now()),
+            timestamp: SystemTime::now(),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::fmt::{self, Display};
+
+/// SI prefixes with their power of ten, from smallest to largest.
+const PREFIXES: [(i32, &str); 8] = [
+    (-12, "p"),
+    (-9, "n"),
+    (-6, "µ"),
+    (-3, "m"),
+    (0, ""),
+    (3, "k"),
+    (6, "M"),
+    (9, "G"),
+];
+
+/// A value in a base unit, displayed with a fitting SI prefix, e.g. `0.0012` A as `1.200 mA`.
+///
+/// The precision (default: 3) sets the number of decimal places after scaling.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Si<'a> {
+    pub value: f64,
+    pub unit: &'a str,
+}
+
+pub fn si(value: f64, unit: &str) -> Si<'_> {
+    Si { value, unit }
+}
+
+impl Display for Si<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(3);
+
+        let magnitude = self.value.abs();
+        let mut index = PREFIXES.iter().position(|(exp, _)| *exp == 0).unwrap();
+        if magnitude.is_normal() {
+            let exp = (magnitude.log10() / 3.0).floor() as i32 * 3;
+            index = PREFIXES
+                .iter()
+                .rposition(|(prefix_exp, _)| *prefix_exp <= exp)
+                .unwrap_or(0);
+
+            // Move up a prefix if rounding would show e.g. `1000.000 mA`.
+            let rounding = 10_f64.powi(precision as i32);
+            let scaled = magnitude / 10_f64.powi(PREFIXES[index].0);
+            if index + 1 < PREFIXES.len() && (scaled * rounding).round() >= 1000.0 * rounding {
+                index += 1;
+            }
+        }
+
+        let (exp, prefix) = PREFIXES[index];
+        write!(
+            f,
+            "{:.*} {prefix}{}",
+            precision,
+            self.value / 10_f64.powi(exp),
+            self.unit
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::si;
+
+    #[test]
+    fn picks_prefix() {
+        assert_eq!(si(0.0012, "A").to_string(), "1.200 mA");
+        assert_eq!(si(12.5, "V").to_string(), "12.500 V");
+        assert_eq!(si(4.7e-6, "F").to_string(), "4.700 µF");
+        assert_eq!(si(2.2e6, "Ω").to_string(), "2.200 MΩ");
+    }
+
+    #[test]
+    fn rounds_up_to_next_prefix() {
+        assert_eq!(si(0.9999996, "A").to_string(), "1.000 A");
+        assert_eq!(si(0.0009994, "A").to_string(), "999.400 µA");
+    }
+
+    #[test]
+    fn zero() {
+        assert_eq!(si(0.0, "A").to_string(), "0.000 A");
+    }
+
+    #[test]
+    fn negative() {
+        assert_eq!(si(-0.0012, "A").to_string(), "-1.200 mA");
+        assert_eq!(si(-0.9999996, "A").to_string(), "-1.000 A");
+    }
+
+    #[test]
+    fn below_smallest_prefix() {
+        assert_eq!(si(1e-15, "A").to_string(), "0.001 pA");
+    }
+
+    #[test]
+    fn custom_precision() {
+        assert_eq!(format!("{:.1}", si(0.0012, "A")), "1.2 mA");
+        assert_eq!(format!("{:.0}", si(0.0012, "A")), "1 mA");
+        assert_eq!(format!("{:.1}", si(0.99996, "A")), "1.0 A");
+    }
+}
@@ -0,0 +1,459 @@
+use crate::{
+    meter::{Current, CurrentMeter, CurrentSample, Measurement, MeterError, MeterFlags},
+    psu::{
+        PowerSupply, PowerSupplyError, Protection, PsuLimits, PsuOutput, PsuStatus, RegulationMode,
+    },
+    sample::{Sample, Sequencer},
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::{self, Instant, Interval, MissedTickBehavior};
+
+/// Largest simulation time step. Keeps the oxide forming integration accurate.
+const MAX_STEP: Duration = Duration::from_millis(1);
+/// Leakage of a fully formed capacitor, as a fraction of the initial leakage.
+const RESIDUAL_LEAKAGE_FRACTION: f64 = 1e-4;
+/// Resistance of a shorted or broken down capacitor in Ω
+const SHORT_RESISTANCE: f64 = 0.05;
+/// Interval between two simulated multimeter readings
+const METER_INTERVAL: Duration = Duration::from_millis(333);
+/// Input voltage of the simulated supply in V
+const INPUT_VOLTAGE: f64 = 24.0;
+/// Internal temperature of the simulated supply in °C
+const TEMPERATURE: f64 = 25.0;
+/// Maximum over-voltage protection setting of the simulated supply in V
+const MAX_VOLTAGE_PROTECTION: f64 = 62.0;
+/// Resolution of the simulated supply's voltage settings in V
+const VOLTAGE_RESOLUTION: f64 = 0.001;
+/// Resolution of the simulated supply's current settings in A
+const CURRENT_RESOLUTION: f64 = 0.0001;
+/// Time constant of the current measurement the simulated supply's over-current protection acts on
+const PROTECTION_RESPONSE_TIME: f64 = 0.1;
+
+/// Physical parameters of the simulated electrolytic capacitor.
+#[derive(Debug, Clone, Copy)]
+pub struct CapacitorParams {
+    /// Capacitance in F
+    pub capacitance: f64,
+    /// Equivalent series resistance in Ω
+    pub esr: f64,
+    /// Rated voltage in V
+    pub rated_voltage: f64,
+    /// Leakage current in A at rated voltage before any reforming
+    pub initial_leakage: f64,
+    /// Time constant in s with which the oxide layer re-forms
+    pub forming_time: f64,
+    /// The capacitor breaks down (shorts) above this voltage
+    pub breakdown_voltage: Option<f64>,
+    /// The capacitor is shorted from the start
+    pub shorted: bool,
+}
+
+/// Creates a simulated power supply and multimeter, both connected to the same capacitor.
+///
+/// `speed` scales simulated time relative to wall clock time.
+pub fn new_pair(params: CapacitorParams, speed: f64) -> (SimPsu, SimMeter) {
+    let sim = Arc::new(Mutex::new(Simulation::new(params, speed)));
+
+    let mut interval = time::interval(METER_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    (
+        SimPsu {
+            sim: sim.clone(),
+            sequencer: Sequencer::default(),
+        },
+        SimMeter {
+            sim,
+            interval,
+            sequencer: Sequencer::default(),
+        },
+    )
+}
+
+#[derive(Debug)]
+struct Simulation {
+    params: CapacitorParams,
+    speed: f64,
+    last_update: Instant,
+
+    /// Conductance of the damaged part of the oxide layer in S
+    defect_conductance: f64,
+    /// Conductance of the intact oxide layer in S
+    residual_conductance: f64,
+
+    /// Voltage across the capacitance (without ESR) in V
+    cap_voltage: f64,
+    /// Voltage up to which the oxide layer is intact in V
+    formed_voltage: f64,
+    shorted: bool,
+
+    output: bool,
+    protection: Protection,
+    regulation_mode: RegulationMode,
+    set_voltage: f64,
+    set_current: f64,
+    voltage_protection: f64,
+    current_protection: f64,
+    /// Current flowing into the capacitor in A
+    current: f64,
+    /// Current as measured by the supply, i.e. low-pass filtered
+    measured_current: f64,
+}
+
+impl Simulation {
+    fn new(params: CapacitorParams, speed: f64) -> Self {
+        let defect_conductance = params.initial_leakage / params.rated_voltage;
+
+        Self {
+            params,
+            speed,
+            last_update: Instant::now(),
+            defect_conductance,
+            residual_conductance: defect_conductance * RESIDUAL_LEAKAGE_FRACTION,
+            cap_voltage: 0.0,
+            formed_voltage: 0.0,
+            shorted: params.shorted,
+            output: false,
+            protection: Protection::None,
+            regulation_mode: RegulationMode::ConstantVoltage,
+            set_voltage: 0.0,
+            set_current: 0.0,
+            voltage_protection: MAX_VOLTAGE_PROTECTION,
+            current_protection: 6.2,
+            current: 0.0,
+            measured_current: 0.0,
+        }
+    }
+
+    /// Advances the simulation to the current time of the Tokio clock, so that tests can run it
+    /// with a paused clock.
+    fn update(&mut self) {
+        let now = Instant::now();
+        let mut remaining = now.duration_since(self.last_update).mul_f64(self.speed);
+        self.last_update = now;
+
+        while !remaining.is_zero() {
+            let dt = remaining.min(MAX_STEP);
+            remaining -= dt;
+            self.step(dt.as_secs_f64());
+        }
+    }
+
+    fn step(&mut self, dt: f64) {
+        let CapacitorParams {
+            capacitance, esr, ..
+        } = self.params;
+
+        // Leakage is modelled as `conductance * v - offset`, which is exact for the piecewise
+        // linear leakage curve as long as the capacitor voltage doesn't cross the formed voltage
+        // within one step.
+        let (conductance, offset) = if self.shorted {
+            (1.0 / SHORT_RESISTANCE, 0.0)
+        } else if self.cap_voltage > self.formed_voltage {
+            (
+                self.residual_conductance + self.defect_conductance,
+                self.defect_conductance * self.formed_voltage,
+            )
+        } else {
+            (self.residual_conductance, 0.0)
+        };
+
+        // Implicit Euler, the ESR time constant is far shorter than any sensible step.
+        let c_dt = capacitance / dt;
+        let cv_voltage = (c_dt * self.cap_voltage + self.set_voltage / esr + offset)
+            / (c_dt + 1.0 / esr + conductance);
+        let cv_current = (self.set_voltage - cv_voltage) / esr;
+
+        // The supply can't sink current, and goes into CC mode above its current setpoint.
+        let constant_voltage = self.output && (0.0..=self.set_current).contains(&cv_current);
+        self.regulation_mode = if self.output && cv_current > self.set_current {
+            RegulationMode::ConstantCurrent
+        } else {
+            RegulationMode::ConstantVoltage
+        };
+        if constant_voltage {
+            self.cap_voltage = cv_voltage;
+            self.current = cv_current;
+        } else {
+            let current = if self.output { self.set_current } else { 0.0 };
+            let current = current.min(cv_current.max(0.0));
+            self.cap_voltage = (c_dt * self.cap_voltage + current + offset) / (c_dt + conductance);
+            self.current = current;
+        }
+
+        if self.cap_voltage > self.formed_voltage {
+            let forming_time = self.params.forming_time;
+            self.formed_voltage += (self.cap_voltage - self.formed_voltage) * dt / forming_time;
+        }
+
+        if let Some(breakdown_voltage) = self.params.breakdown_voltage {
+            if !self.shorted && self.cap_voltage > breakdown_voltage {
+                println!("Simulated capacitor broke down at {:.2}V", self.cap_voltage);
+                self.shorted = true;
+            }
+        }
+
+        self.measured_current +=
+            (self.current - self.measured_current) * dt / PROTECTION_RESPONSE_TIME;
+
+        if self.output {
+            if self.output_voltage() > self.voltage_protection {
+                self.protection = Protection::OverVoltage;
+                self.output = false;
+            } else if self.measured_current > self.current_protection {
+                self.protection = Protection::OverCurrent;
+                self.output = false;
+            }
+        }
+    }
+
+    /// Voltage at the terminals of the capacitor in V
+    fn output_voltage(&self) -> f64 {
+        self.cap_voltage + self.current * self.params.esr
+    }
+}
+
+/// Simulated power supply, see [`new_pair`].
+pub struct SimPsu {
+    sim: Arc<Mutex<Simulation>>,
+    sequencer: Sequencer,
+}
+
+impl SimPsu {
+    fn with_sim<T>(&self, f: impl FnOnce(&mut Simulation) -> T) -> T {
+        let mut sim = self.sim.lock().unwrap();
+        sim.update();
+        f(&mut sim)
+    }
+}
+
+impl PowerSupply for SimPsu {
+    fn limits(&self) -> PsuLimits {
+        PsuLimits {
+            max_voltage_protection: MAX_VOLTAGE_PROTECTION,
+            voltage_resolution: VOLTAGE_RESOLUTION,
+            current_resolution: CURRENT_RESOLUTION,
+        }
+    }
+
+    async fn set_output(&mut self, enable: bool) -> Result<(), PowerSupplyError> {
+        self.with_sim(|sim| {
+            sim.output = enable;
+            if enable {
+                sim.protection = Protection::None;
+            }
+        });
+        Ok(())
+    }
+
+    async fn set_voltage(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+        self.with_sim(|sim| sim.set_voltage = voltage);
+        Ok(())
+    }
+
+    async fn set_current(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+        self.with_sim(|sim| sim.set_current = current);
+        Ok(())
+    }
+
+    async fn set_voltage_protection(&mut self, voltage: f64) -> Result<(), PowerSupplyError> {
+        self.with_sim(|sim| sim.voltage_protection = voltage);
+        Ok(())
+    }
+
+    async fn set_current_protection(&mut self, current: f64) -> Result<(), PowerSupplyError> {
+        self.with_sim(|sim| sim.current_protection = current);
+        Ok(())
+    }
+
+    async fn voltage_and_current(&mut self) -> Result<Sample<PsuOutput>, PowerSupplyError> {
+        let output = self.with_sim(|sim| PsuOutput {
+            voltage: sim.output_voltage(),
+            current: sim.current,
+        });
+        Ok(self.sequencer.sample(output))
+    }
+
+    async fn status(&mut self) -> Result<Sample<PsuStatus>, PowerSupplyError> {
+        let status = self.with_sim(|sim| PsuStatus {
+            protection: sim.protection,
+            regulation_mode: sim.regulation_mode,
+            output_enabled: sim.output,
+            input_voltage: INPUT_VOLTAGE,
+            temperature: TEMPERATURE,
+        });
+        Ok(self.sequencer.sample(status))
+    }
+
+    async fn voltage_and_current_protection(&mut self) -> Result<(f64, f64), PowerSupplyError> {
+        Ok(self.with_sim(|sim| (sim.voltage_protection, sim.current_protection)))
+    }
+
+    async fn disconnect(&mut self) -> Result<(), PowerSupplyError> {
+        Ok(())
+    }
+}
+
+/// Simulated multimeter in series with the capacitor, see [`new_pair`].
+pub struct SimMeter {
+    sim: Arc<Mutex<Simulation>>,
+    interval: Interval,
+    sequencer: Sequencer,
+}
+
+impl CurrentMeter for SimMeter {
+    async fn next_sample(&mut self) -> Result<CurrentSample, MeterError> {
+        self.interval.tick().await;
+
+        let mut sim = self.sim.lock().unwrap();
+        sim.update();
+
+        Ok(self.sequencer.sample(Current {
+            amperes: Measurement::Value(sim.current),
+            flags: MeterFlags::default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: CapacitorParams = CapacitorParams {
+        capacitance: 100e-6,
+        esr: 0.5,
+        rated_voltage: 10.0,
+        initial_leakage: 1e-3,
+        forming_time: 10.0,
+        breakdown_voltage: None,
+        shorted: false,
+    };
+
+    /// Runs the simulation for `secs` seconds in steps of [`MAX_STEP`].
+    fn run(sim: &mut Simulation, secs: f64) {
+        for _ in 0..(secs / MAX_STEP.as_secs_f64()).round() as usize {
+            sim.step(MAX_STEP.as_secs_f64());
+        }
+    }
+
+    /// Switches on the output at `voltage`, with a current limit of 1A.
+    fn switch_on(params: CapacitorParams, voltage: f64) -> Simulation {
+        let mut sim = Simulation::new(params, 1.0);
+        sim.set_voltage = voltage;
+        sim.set_current = 1.0;
+        sim.output = true;
+        sim
+    }
+
+    #[test]
+    fn charges_to_set_voltage() {
+        let mut sim = switch_on(PARAMS, 5.0);
+        run(&mut sim, 1.0);
+        assert!((sim.output_voltage() - 5.0).abs() < 1e-3, "{sim:?}");
+        assert_eq!(sim.regulation_mode, RegulationMode::ConstantVoltage);
+        assert_eq!(sim.protection, Protection::None);
+    }
+
+    #[test]
+    fn leakage_decays_while_forming() {
+        let mut sim = switch_on(PARAMS, PARAMS.rated_voltage);
+        run(&mut sim, 1.0);
+        let initial = sim.current;
+        assert!(initial > 0.5 * PARAMS.initial_leakage, "{sim:?}");
+
+        run(&mut sim, 3.0 * PARAMS.forming_time);
+        let formed = sim.current;
+        assert!(formed < 0.1 * initial, "{initial} -> {formed}");
+        let residual = PARAMS.initial_leakage * RESIDUAL_LEAKAGE_FRACTION;
+        assert!(formed > residual, "{formed}");
+    }
+
+    #[test]
+    fn formed_capacitor_leaks_below_formed_voltage() {
+        let mut sim = switch_on(PARAMS, PARAMS.rated_voltage);
+        run(&mut sim, 5.0 * PARAMS.forming_time);
+        let formed = sim.current;
+
+        sim.set_voltage = PARAMS.rated_voltage / 2.0;
+        run(&mut sim, 1.0);
+        assert!(sim.current < formed, "{formed} -> {}", sim.current);
+    }
+
+    #[test]
+    fn breaks_down_above_breakdown_voltage() {
+        let params = CapacitorParams {
+            breakdown_voltage: Some(5.0),
+            ..PARAMS
+        };
+        let mut sim = switch_on(params, 4.0);
+        run(&mut sim, 1.0);
+        assert!(!sim.shorted);
+
+        sim.set_current = 0.05;
+        sim.set_voltage = 6.0;
+        run(&mut sim, 1.0);
+        assert!(sim.shorted);
+        assert_eq!(sim.regulation_mode, RegulationMode::ConstantCurrent);
+        assert!((sim.current - 0.05).abs() < 1e-9, "{sim:?}");
+    }
+
+    #[test]
+    fn shorted_capacitor_draws_set_current() {
+        let params = CapacitorParams {
+            shorted: true,
+            ..PARAMS
+        };
+        let mut sim = switch_on(params, 5.0);
+        sim.set_current = 0.02;
+        run(&mut sim, 1.0);
+        assert_eq!(sim.regulation_mode, RegulationMode::ConstantCurrent);
+        assert!((sim.current - 0.02).abs() < 1e-9, "{sim:?}");
+        assert!(sim.output_voltage() < 0.05, "{sim:?}");
+    }
+
+    #[test]
+    fn over_current_protection_trips() {
+        let params = CapacitorParams {
+            shorted: true,
+            ..PARAMS
+        };
+        let mut sim = switch_on(params, 5.0);
+        sim.set_current = 0.05;
+        sim.current_protection = 0.01;
+        run(&mut sim, 1.0);
+        assert_eq!(sim.protection, Protection::OverCurrent);
+        assert!(!sim.output);
+    }
+
+    #[test]
+    fn over_voltage_protection_trips() {
+        let mut sim = switch_on(PARAMS, 10.0);
+        sim.voltage_protection = 5.0;
+        run(&mut sim, 1.0);
+        assert_eq!(sim.protection, Protection::OverVoltage);
+        assert!(!sim.output);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn follows_tokio_clock_at_speed() {
+        let mut sim = Simulation::new(PARAMS, 10.0);
+        sim.set_voltage = PARAMS.rated_voltage;
+        sim.set_current = 1.0;
+        sim.output = true;
+
+        time::advance(Duration::from_millis(100)).await;
+        sim.update();
+        let after_one_second = sim.formed_voltage;
+
+        let mut reference = switch_on(PARAMS, PARAMS.rated_voltage);
+        run(&mut reference, 1.0);
+        assert!(
+            (after_one_second - reference.formed_voltage).abs() < 1e-9,
+            "{after_one_second} != {}",
+            reference.formed_voltage
+        );
+    }
+}